@@ -0,0 +1,56 @@
+//! Compares the draw calls and frame times of the batched renderer
+//! with the one issuing a call for each sprite.
+//!
+//! `cargo run --release --example draw_calls`
+
+extern crate ggez;
+extern crate skii;
+
+use std::{env, path};
+use std::time::Instant;
+
+use ggez::ContextBuilder;
+use ggez::conf;
+use ggez::event::EventHandler;
+use skii::loader::Pack;
+use skii::states::Playing;
+
+const FRAMES: u32 = 300;
+
+fn main() {
+    let mut assets = path::PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    assets.push("resources");
+
+    let cb = ContextBuilder::new("Skii", "Piripant")
+        .window_setup(conf::WindowSetup::default().title("Skii draw calls"))
+        .window_mode(conf::WindowMode::default().dimensions(720, 720))
+        .add_resource_path(&assets);
+
+    let ctx = &mut cb.build().unwrap();
+    let pack = Pack::load(&assets).expect("Could not load the base pack");
+    let state = &mut skii::renderer::ViewState::new(ctx, &[pack]).unwrap();
+    // Measure the slope, not the main menu
+    state.set_state(Box::new(Playing));
+
+    println!("{:>10} {:>12} {:>12}", "renderer", "draw calls", "frame (ms)");
+    for &(name, batched) in &[("sprites", false), ("batched", true)] {
+        state.batched = batched;
+
+        let mut draw_calls = 0;
+        let start = Instant::now();
+        for _ in 0..FRAMES {
+            state.draw(ctx).unwrap();
+            draw_calls += state.draw_calls;
+        }
+        let elapsed = start.elapsed();
+        let elapsed_ms =
+            elapsed.as_secs() as f64 * 1000.0 + f64::from(elapsed.subsec_nanos()) * 1e-6;
+
+        println!(
+            "{:>10} {:>12} {:>12.3}",
+            name,
+            draw_calls / FRAMES,
+            elapsed_ms / f64::from(FRAMES)
+        );
+    }
+}
@@ -0,0 +1,105 @@
+//! Trains a linear policy on the learning environment with random hill climbing:
+//! the weights are changed at random, and the change is kept when the policy skis further.
+//!
+//! `cargo run --release --example train`
+
+extern crate rand;
+extern crate skii;
+
+use std::env;
+use std::path::PathBuf;
+
+use rand::Rng;
+use skii::env::{Env, EnvSettings, Observation, ACTION_COUNT};
+use skii::loader::{load_resources, Pack};
+use skii::random::SeededRng;
+
+// The number of changes tried
+const ITERATIONS: usize = 200;
+// The episodes each policy is scored on, always on the same slopes
+const EPISODES: u64 = 5;
+// How much the weights are changed each time
+const STEP_SIZE: f32 = 0.1;
+
+/// Chooses the action with the highest weighted sum of the observation
+struct Policy {
+    // A row of weights for each action
+    weights: Vec<Vec<f32>>,
+}
+
+impl Policy {
+    fn action(&self, observation: &Observation) -> usize {
+        let values = observation.to_vec();
+        let scores = self.weights
+            .iter()
+            .map(|row| row.iter().zip(&values).map(|(w, v)| w * v).sum::<f32>());
+
+        let mut best = (0, std::f32::MIN);
+        for (action, score) in scores.enumerate() {
+            if score > best.1 {
+                best = (action, score);
+            }
+        }
+        best.0
+    }
+
+    /// Returns the average reward of the episodes
+    fn evaluate(&self, env: &mut Env) -> f32 {
+        let mut total = 0.0;
+        for seed in 0..EPISODES {
+            let mut observation = env.reset(seed);
+            loop {
+                let input = Env::action(self.action(&observation));
+                let (next, reward, done) = env.step(&input);
+                total += reward;
+                observation = next;
+                if done {
+                    break;
+                }
+            }
+        }
+
+        total / EPISODES as f32
+    }
+}
+
+fn main() {
+    let mut assets = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    assets.push("resources");
+    let pack = Pack::load(&assets).expect("Could not load the base pack");
+    let (resources, _) = load_resources(&[pack]);
+
+    let mut settings = EnvSettings::new();
+    settings.max_ticks = 1800;
+    let mut environment = Env::new(resources, settings);
+
+    let size = environment.observation_size();
+    let mut policy = Policy {
+        weights: vec![vec![0.0; size]; ACTION_COUNT],
+    };
+    let mut best_reward = policy.evaluate(&mut environment);
+    let mut rng = SeededRng::new(0);
+
+    for iteration in 0..ITERATIONS {
+        let candidate = Policy {
+            weights: policy
+                .weights
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .map(|w| w + rng.gen_range(-STEP_SIZE, STEP_SIZE))
+                        .collect()
+                })
+                .collect(),
+        };
+
+        let reward = candidate.evaluate(&mut environment);
+        if reward > best_reward {
+            best_reward = reward;
+            policy = candidate;
+            println!("{:>5} average reward {:.2}", iteration, best_reward);
+        }
+    }
+
+    println!("best average reward {:.2}", best_reward);
+}
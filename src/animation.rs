@@ -0,0 +1,103 @@
+use atlas::Sprite;
+use game::Player;
+
+// The angular velocity over which the player is shown carving
+const CARVE_SPEED: f32 = 1.0;
+
+/// What happens when an animation reaches its last frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoopMode {
+    // Starts again from the first frame
+    Loop,
+    // Stays on the last frame
+    Once,
+    // Goes back to the first frame, and then forward again
+    PingPong,
+}
+
+/// A sequence of frames cut from a sprite sheet
+pub struct Animation {
+    pub frames: Vec<Sprite>,
+    // How many seconds each frame is shown
+    pub frame_time: f32,
+    pub loop_mode: LoopMode,
+}
+
+impl Animation {
+    /// Returns the frame shown after `time` seconds from the start of the animation
+    pub fn frame(&self, time: f32) -> Sprite {
+        let count = self.frames.len();
+        let step = (time.max(0.0) / self.frame_time) as usize;
+
+        let index = match self.loop_mode {
+            LoopMode::Loop => step % count,
+            LoopMode::Once => step.min(count - 1),
+            LoopMode::PingPong => {
+                if count == 1 {
+                    0
+                } else {
+                    let period = (count - 1) * 2;
+                    let position = step % period;
+                    if position < count {
+                        position
+                    } else {
+                        period - position
+                    }
+                }
+            }
+        };
+
+        self.frames[index]
+    }
+}
+
+/// The animations of a type, each one played in a different state.
+/// Eg: the player has "straight", "carve_left" and "crashed"
+pub struct Animations {
+    states: Vec<(String, Animation)>,
+}
+
+impl Animations {
+    pub fn new() -> Animations {
+        Animations { states: Vec::new() }
+    }
+
+    pub fn insert(&mut self, state: String, animation: Animation) {
+        self.states.push((state, animation));
+    }
+
+    pub fn get(&self, state: &str) -> Option<&Animation> {
+        self.states
+            .iter()
+            .find(|&&(ref name, _)| name == state)
+            .map(|&(_, ref animation)| animation)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    /// Returns the frame of the state animation after `time` seconds,
+    /// or the fallback sprite when the state has no animation
+    pub fn frame_or(&self, state: &str, time: f32, fallback: Sprite) -> Sprite {
+        match self.get(state) {
+            Some(animation) => animation.frame(time),
+            None => fallback,
+        }
+    }
+}
+
+/// Returns the animation state of the player
+pub fn player_state(player: &Player, crashed: bool) -> &'static str {
+    if crashed {
+        "crashed"
+    } else if player.tucking {
+        "tuck"
+    } else if player.angular_velocity < -CARVE_SPEED {
+        "carve_left"
+    } else if player.angular_velocity > CARVE_SPEED {
+        "carve_right"
+    } else {
+        "straight"
+    }
+}
@@ -0,0 +1,273 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+#[cfg(feature = "window")]
+use ggez::{Context, GameResult};
+#[cfg(feature = "window")]
+use ggez::graphics;
+#[cfg(feature = "window")]
+use ggez::graphics::{DrawParam, FilterMode, Image};
+#[cfg(feature = "window")]
+use ggez::graphics::spritebatch::SpriteBatch;
+use image::{GenericImage, RgbaImage};
+use serde_json;
+use types::Rect;
+
+// The maximum size of each atlas page, in pixels
+const PAGE_SIZE: u32 = 1024;
+// The empty pixels around each texture, to avoid bleeding from the neighbors
+const PADDING: u32 = 1;
+
+/// Where a texture is in the atlas
+#[derive(Clone, Copy, Debug)]
+pub struct Sprite {
+    // The page holding the texture
+    pub page: usize,
+    // The rectangle of the texture, normalized in the 0.0..1.0 range of the page
+    pub region: Rect,
+    // The size of the texture in pixels
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Sprite {
+    /// Returns the part of the sprite inside the rectangle, in pixels
+    pub fn sub_sprite(&self, x: u32, y: u32, width: u32, height: u32) -> Sprite {
+        let pixel_width = self.region.w / self.width as f32;
+        let pixel_height = self.region.h / self.height as f32;
+
+        Sprite {
+            page: self.page,
+            region: Rect::new(
+                self.region.x + x as f32 * pixel_width,
+                self.region.y + y as f32 * pixel_height,
+                width as f32 * pixel_width,
+                height as f32 * pixel_height,
+            ),
+            width,
+            height,
+        }
+    }
+}
+
+/// Collects the textures to be packed in an atlas
+pub struct AtlasBuilder {
+    textures: Vec<(String, RgbaImage)>,
+}
+
+impl AtlasBuilder {
+    pub fn new() -> AtlasBuilder {
+        AtlasBuilder {
+            textures: Vec::new(),
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.textures.iter().any(|&(ref other, _)| other == name)
+    }
+
+    /// Adds the texture, unless one with the same name is already present
+    pub fn add(&mut self, name: &str, texture: RgbaImage) {
+        if !self.contains(name) {
+            self.textures.push((name.to_owned(), texture));
+        }
+    }
+
+    /// Packs the textures in shelves, from the tallest to the shortest.
+    /// When a page is full a new one is started
+    pub fn build(mut self) -> Atlas {
+        self.textures
+            .sort_by_key(|&(_, ref texture)| -(texture.height() as i64));
+
+        // The position in pixels of each texture
+        let mut placed: Vec<(String, usize, u32, u32, RgbaImage)> = Vec::new();
+        // The used size of each page
+        let mut page_sizes: Vec<(u32, u32)> = Vec::new();
+
+        let (mut page, mut x, mut y, mut shelf_height) = (0, 0, 0, 0);
+        for (name, texture) in self.textures {
+            let width = texture.width() + PADDING * 2;
+            let height = texture.height() + PADDING * 2;
+
+            // Start a new shelf when the texture does not fit on this one
+            if x + width > PAGE_SIZE {
+                x = 0;
+                y += shelf_height;
+                shelf_height = 0;
+            }
+            // Start a new page when the shelf does not fit on this one
+            if y + height > PAGE_SIZE && (x, y) != (0, 0) {
+                page += 1;
+                x = 0;
+                y = 0;
+                shelf_height = 0;
+            }
+
+            if page_sizes.len() <= page {
+                page_sizes.push((0, 0));
+            }
+            let size = &mut page_sizes[page];
+            size.0 = size.0.max(x + width);
+            size.1 = size.1.max(y + height);
+
+            placed.push((name, page, x + PADDING, y + PADDING, texture));
+            x += width;
+            shelf_height = shelf_height.max(height);
+        }
+
+        let mut pages: Vec<RgbaImage> = page_sizes
+            .iter()
+            .map(|&(width, height)| RgbaImage::new(width, height))
+            .collect();
+        let mut sprites = Vec::new();
+        for (name, page, x, y, texture) in placed {
+            pages[page].copy_from(&texture, x, y);
+
+            let (page_width, page_height) = page_sizes[page];
+            let sprite = Sprite {
+                page,
+                region: Rect::new(
+                    x as f32 / page_width as f32,
+                    y as f32 / page_height as f32,
+                    texture.width() as f32 / page_width as f32,
+                    texture.height() as f32 / page_height as f32,
+                ),
+                width: texture.width(),
+                height: texture.height(),
+            };
+            sprites.push((name, sprite));
+        }
+
+        Atlas { pages, sprites }
+    }
+}
+
+/// Several textures packed in a few images,
+/// so that they can be drawn with a batch for each page
+pub struct Atlas {
+    pub pages: Vec<RgbaImage>,
+    // The name of each texture and where it was packed
+    pub sprites: Vec<(String, Sprite)>,
+}
+
+impl Atlas {
+    /// Returns where the texture with the given name was packed
+    pub fn sprite(&self, name: &str) -> Option<Sprite> {
+        self.sprites
+            .iter()
+            .find(|&&(ref other, _)| other == name)
+            .map(|&(_, sprite)| sprite)
+    }
+
+    /// Creates an image for each page, to be drawn on the screen
+    #[cfg(feature = "window")]
+    pub fn upload(&self, ctx: &mut Context) -> GameResult<Vec<Image>> {
+        let mut images = Vec::new();
+        for page in &self.pages {
+            let mut image = Image::from_rgba8(
+                ctx,
+                page.width() as u16,
+                page.height() as u16,
+                &page.clone().into_raw(),
+            )?;
+            image.set_filter(FilterMode::Nearest);
+            images.push(image);
+        }
+
+        Ok(images)
+    }
+
+    /// Saves each page as a png in the directory,
+    /// together with a manifest.json describing where each texture is
+    pub fn save(&self, directory: &Path) -> io::Result<()> {
+        fs::create_dir_all(directory)?;
+
+        let mut pages_json = Vec::new();
+        for (i, page) in self.pages.iter().enumerate() {
+            let file_name = format!("atlas{}.png", i);
+            page.save(directory.join(&file_name))?;
+            pages_json.push(json!({
+                "file": file_name,
+                "width": page.width(),
+                "height": page.height(),
+            }));
+        }
+
+        let mut sprites_json = serde_json::Map::new();
+        for &(ref name, ref sprite) in &self.sprites {
+            let (page_width, page_height) = self.pages[sprite.page].dimensions();
+            sprites_json.insert(
+                name.clone(),
+                json!({
+                    "page": sprite.page,
+                    "x": (sprite.region.x * page_width as f32).round() as u32,
+                    "y": (sprite.region.y * page_height as f32).round() as u32,
+                    "width": sprite.width,
+                    "height": sprite.height,
+                }),
+            );
+        }
+
+        let manifest = json!({
+            "pages": pages_json,
+            "sprites": sprites_json,
+        });
+        let file = File::create(directory.join("manifest.json"))?;
+        serde_json::to_writer_pretty(file, &manifest)?;
+
+        Ok(())
+    }
+}
+
+/// A sprite batch for each page of an atlas
+#[cfg(feature = "window")]
+pub struct AtlasBatch {
+    batches: Vec<SpriteBatch>,
+    // Whether each batch has sprites to draw
+    used: Vec<bool>,
+}
+
+#[cfg(feature = "window")]
+impl AtlasBatch {
+    pub fn new(pages: &[Image]) -> AtlasBatch {
+        AtlasBatch {
+            batches: pages
+                .iter()
+                .map(|page| SpriteBatch::new(page.clone()))
+                .collect(),
+            used: vec![false; pages.len()],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for batch in &mut self.batches {
+            batch.clear();
+        }
+        for used in &mut self.used {
+            *used = false;
+        }
+    }
+
+    /// Adds the sprite to the batch of its page, the source rectangle is set by the sprite
+    pub fn add(&mut self, sprite: &Sprite, param: DrawParam) {
+        self.batches[sprite.page].add(DrawParam {
+            src: sprite.region,
+            ..param
+        });
+        self.used[sprite.page] = true;
+    }
+
+    /// Draws the pages with sprites, returns the number of draw calls issued
+    pub fn draw(&self, ctx: &mut Context, param: DrawParam) -> GameResult<u32> {
+        let mut draw_calls = 0;
+        for (batch, &used) in self.batches.iter().zip(&self.used) {
+            if used {
+                graphics::draw_ex(ctx, batch, param)?;
+                draw_calls += 1;
+            }
+        }
+
+        Ok(draw_calls)
+    }
+}
@@ -0,0 +1,138 @@
+use std::f32;
+use game::{PlayerInput, World};
+use simulation::Controller;
+
+// The steering tried by the autopilot, from left to right
+const STEERINGS: [f32; 5] = [-1.0, -0.5, 0.0, 0.5, 1.0];
+// The steering tried after the first input, to check that the player can still turn away
+const FOLLOW_STEERINGS: [f32; 3] = [-1.0, 0.0, 1.0];
+// The updates each input is held for in the predictions
+const HOLD_TICKS: u32 = 20;
+// The updates an input is kept for before choosing a new one
+const REPLAN_TICKS: u32 = 5;
+// The length of the updates predicted, the same as the game ones
+const PREDICTION_DT: f32 = 1.0 / 60.0;
+// How much surviving one more update is worth, compared to a meter skied
+const SURVIVAL_WEIGHT: f32 = 10.0;
+// How much staying away from the center of the slope costs, for each meter
+const CENTER_WEIGHT: f32 = 0.3;
+
+/// Skis down the slope on its own, trying the possible inputs on copies of the player
+/// and choosing the one going furthest without crashing
+pub struct Autopilot {
+    current: PlayerInput,
+    // The updates left before choosing a new input
+    ticks_left: u32,
+}
+
+impl Autopilot {
+    pub fn new() -> Autopilot {
+        Autopilot {
+            current: PlayerInput::none(),
+            ticks_left: 0,
+        }
+    }
+
+    /// Returns the input with the best outcome, holding it and then turning
+    fn plan(&self, world: &World) -> PlayerInput {
+        let mut best = (PlayerInput::none(), f32::MIN);
+
+        for &steering in &STEERINGS {
+            for &(tucking, braking) in &[(false, false), (true, false), (false, true)] {
+                let input = PlayerInput {
+                    steering,
+                    tucking,
+                    braking,
+                };
+
+                // A move is as good as the best way of continuing it
+                let score = FOLLOW_STEERINGS
+                    .iter()
+                    .map(|&steering| {
+                        let follow = PlayerInput {
+                            steering,
+                            ..PlayerInput::none()
+                        };
+                        outcome(world, &[input, follow])
+                    })
+                    .fold(f32::MIN, f32::max);
+
+                if score > best.1 {
+                    best = (input, score);
+                }
+            }
+        }
+
+        best.0
+    }
+}
+
+/// Predicts the player holding each input for HOLD_TICKS, higher scores are better
+fn outcome(world: &World, inputs: &[PlayerInput]) -> f32 {
+    let mut player = world.player.clone();
+    let start_y = player.position.y;
+
+    let mut survived = 0;
+    'inputs: for input in inputs {
+        for _ in 0..HOLD_TICKS {
+            if world.predict(&mut player, input, PREDICTION_DT).is_some() {
+                break 'inputs;
+            }
+            survived += 1;
+        }
+    }
+
+    let center = world.width() as f32 / 2.0;
+    survived as f32 * SURVIVAL_WEIGHT + (player.position.y - start_y)
+        - (player.position.x - center).abs() * CENTER_WEIGHT
+}
+
+impl Controller for Autopilot {
+    fn input(&mut self, world: &World) -> PlayerInput {
+        if self.ticks_left == 0 {
+            self.current = self.plan(world);
+            self.ticks_left = REPLAN_TICKS;
+        }
+        self.ticks_left -= 1;
+
+        self.current
+    }
+
+    fn reset(&mut self) {
+        *self = Autopilot::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use game::DEFAULT_SLOPE_WIDTH;
+    use loader::base_resources;
+    use map::Map;
+    use simulation::{new_world, simulate_run, StraightController};
+    use types::Vector2;
+
+    #[test]
+    fn steers_around_an_object_ahead() {
+        let width = DEFAULT_SLOPE_WIDTH;
+        let mut world = new_world(base_resources(), "flat", width, 16);
+        // A tree in the way of the player, on a slope with nothing else after it
+        let center = width as f32 / 2.0;
+        let map = Map {
+            tiles: vec![vec!["snow".to_owned(); width as usize]; 20],
+            objects: vec![("tree1".to_owned(), Vector2::new(center, 12.0), 0.0)],
+        };
+
+        let mut run = |controller: &mut Controller| {
+            simulate_run(&mut world, controller, Some(&map), 7, 0, 900, PREDICTION_DT)
+        };
+
+        let straight = run(&mut StraightController);
+        assert_eq!(straight.crash, Some("tree1".to_owned()));
+
+        let autopilot = run(&mut Autopilot::new());
+        assert_eq!(autopilot.crash, None);
+        assert!(autopilot.ticks > straight.ticks);
+        assert!(autopilot.distance > straight.distance);
+    }
+}
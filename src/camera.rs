@@ -0,0 +1,164 @@
+use game::World;
+use types::{Point2, Vector2};
+use rand;
+use rand::Rng;
+
+// How quickly the camera reaches its target, higher is snappier
+const FOLLOW_SPEED: f32 = 6.0;
+// How many tiles ahead of the player the camera looks for each unit of speed
+const LOOK_AHEAD: f32 = 0.4;
+const MAX_LOOK_AHEAD: f32 = 3.0;
+// How much the camera zooms out for each unit of speed, and the furthest it goes
+const ZOOM_OUT: f32 = 0.04;
+const MIN_ZOOM: f32 = 0.7;
+// The fewest rows of tiles always visible on the screen
+const MIN_VISIBLE_ROWS: f32 = 9.0;
+// The part of the window width covered by the slope
+const SLOPE_COVERAGE: f32 = 0.78;
+// The distance in pixels of the strongest shake
+const MAX_SHAKE: f32 = 20.0;
+// How much of the shake fades away each second
+const SHAKE_DECAY: f32 = 1.5;
+
+/// Decides which part of the world is shown on the screen
+pub struct Camera {
+    // The world point at the center of the screen
+    pub position: Vector2,
+    // 1.0 shows the world at the base scale, lower values show more of it
+    pub zoom: f32,
+    // The scale of the sprites at zoom 1.0, chosen to fit the window
+    pub base_scale: f32,
+    pub window_size: (u32, u32),
+    // How many tiles there are between the player and the bottom of the screen
+    pub offset_y: f32,
+    // From 0.0 to 1.0, how strongly the screen is shaking
+    trauma: f32,
+    // The offset in pixels of the current shake
+    shake_offset: Vector2,
+}
+
+impl Camera {
+    pub fn new(window_size: (u32, u32), base_scale: f32) -> Camera {
+        Camera {
+            position: Vector2::new(0.0, 0.0),
+            zoom: 1.0,
+            base_scale,
+            window_size,
+            offset_y: 2.0,
+            trauma: 0.0,
+            shake_offset: Vector2::new(0.0, 0.0),
+        }
+    }
+
+    /// The scale the sprites are drawn with
+    pub fn scale(&self) -> f32 {
+        self.base_scale * self.zoom
+    }
+
+    /// The size of a tile on the screen, in pixels
+    pub fn tile_size(&self) -> f32 {
+        self.scale() * 16.0
+    }
+
+    /// Returns a point from world space to screen space
+    pub fn world_to_screen(&self, point: &Vector2) -> Point2 {
+        let tile_size = self.tile_size();
+        Point2::new(
+            (point.x - self.position.x) * tile_size + self.window_size.0 as f32 / 2.0
+                + self.shake_offset.x,
+            (self.position.y - point.y) * tile_size + self.window_size.1 as f32 / 2.0
+                + self.shake_offset.y,
+        )
+    }
+
+    /// Returns a point from screen space to world space, eg: the one under the mouse
+    pub fn screen_to_world(&self, point: &Point2) -> Vector2 {
+        let tile_size = self.tile_size();
+        Vector2::new(
+            (point.x - self.window_size.0 as f32 / 2.0 - self.shake_offset.x) / tile_size
+                + self.position.x,
+            self.position.y
+                - (point.y - self.window_size.1 as f32 / 2.0 - self.shake_offset.y) / tile_size,
+        )
+    }
+
+    /// Chooses the scale so that a slope `slope_width` tiles wide fills the window
+    /// without being stretched
+    pub fn fit(&mut self, window_size: (u32, u32), slope_width: u32) {
+        let (width, height) = (window_size.0 as f32, window_size.1 as f32);
+        self.window_size = window_size;
+        self.base_scale = (width * SLOPE_COVERAGE / (slope_width as f32 * 16.0))
+            .min(height / (MIN_VISIBLE_ROWS * 16.0));
+    }
+
+    /// The most rows the camera can show, when fully zoomed out and looking ahead
+    pub fn max_visible_rows(&self) -> f32 {
+        self.window_size.1 as f32 / (self.base_scale * MIN_ZOOM * 16.0) + MAX_LOOK_AHEAD
+    }
+
+    /// Moves the camera towards the player, and fades the shake away
+    pub fn follow(&mut self, world: &World, dt: f32) {
+        let (target, zoom) = self.target(world);
+        // The same fraction of the distance is covered each second, whatever the frame rate
+        let amount = 1.0 - (-FOLLOW_SPEED * dt).exp();
+        self.zoom += (zoom - self.zoom) * amount;
+        self.position += (target - self.position) * amount;
+
+        self.trauma = (self.trauma - SHAKE_DECAY * dt).max(0.0);
+        // Weak shakes are barely visible, strong ones are violent
+        let strength = self.trauma * self.trauma * MAX_SHAKE;
+        let mut rng = rand::thread_rng();
+        self.shake_offset = Vector2::new(
+            rng.gen_range(-1.0, 1.0) * strength,
+            rng.gen_range(-1.0, 1.0) * strength,
+        );
+    }
+
+    /// Moves the camera directly on the player, eg: when a new run starts
+    pub fn snap(&mut self, world: &World) {
+        let (target, zoom) = self.target(world);
+        self.position = target;
+        self.zoom = zoom;
+        self.trauma = 0.0;
+        self.shake_offset = Vector2::new(0.0, 0.0);
+    }
+
+    /// Shakes the screen, `amount` goes from 0.0 to 1.0
+    pub fn shake(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount).min(1.0);
+    }
+
+    /// Moves the camera together with the world
+    pub fn scroll(&mut self, scrolling: u32) {
+        self.position.y -= scrolling as f32;
+    }
+
+    /// Returns where the camera should be, and its zoom
+    fn target(&self, world: &World) -> (Vector2, f32) {
+        let player = &world.player;
+        let speed = player.velocity.norm();
+        let zoom = (1.0 / (1.0 + speed * ZOOM_OUT)).max(MIN_ZOOM);
+        let tile_size = self.base_scale * zoom * 16.0;
+
+        // Slopes narrower than the screen stay centered,
+        // on wider ones the camera follows the player without showing past the edges
+        let visible_columns = self.window_size.0 as f32 / tile_size;
+        let width = world.width() as f32;
+        let x = if width <= visible_columns {
+            width / 2.0
+        } else {
+            player
+                .position
+                .x
+                .max(visible_columns / 2.0)
+                .min(width - visible_columns / 2.0)
+        };
+
+        // The faster the player, the further ahead the camera looks
+        let look_ahead = (player.velocity.y.max(0.0) * LOOK_AHEAD).min(MAX_LOOK_AHEAD);
+        let visible_rows = self.window_size.1 as f32 / tile_size;
+        let y = player.position.y - self.offset_y + visible_rows / 2.0 + look_ahead;
+
+        (Vector2::new(x, y), zoom)
+    }
+}
@@ -0,0 +1,207 @@
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+
+use gif;
+use gif::SetParameter;
+use image::imageops::{self, FilterType};
+use image::RgbaImage;
+use loader::user_file;
+
+// The directory holding the screenshots and recordings, in the user data directory
+const CAPTURES_DIR: &str = "captures";
+// The updates of each second of the game, used to time the recorded frames
+const UPDATES_PER_SECOND: f32 = 60.0;
+// From 1 to 30, higher quantizes the colors of the recorded frames faster but worse
+const QUANTIZE_SPEED: i32 = 10;
+// The frames waiting to be encoded before the game waits for the encoder
+const QUEUED_FRAMES: usize = 60;
+
+/// How the screenshots and recordings are taken
+pub struct CaptureSettings {
+    // The size of the pictures, compared to the window
+    pub scale: f32,
+    // The updates skipped after each recorded frame, higher makes smaller recordings
+    pub frame_skip: u32,
+}
+
+impl CaptureSettings {
+    pub fn new() -> CaptureSettings {
+        CaptureSettings {
+            scale: 1.0,
+            frame_skip: 2,
+        }
+    }
+}
+
+/// An animated gif being written by a thread of its own, a frame at a time
+struct Recording {
+    path: PathBuf,
+    // The pictures waiting to be encoded
+    frames: SyncSender<RgbaImage>,
+    // Stops once the frames are closed, or at the first error
+    worker: JoinHandle<io::Result<()>>,
+    // The updates to skip before the next frame
+    skipping: u32,
+}
+
+/// Saves screenshots and recordings of the game in the user data directory
+pub struct Capture {
+    pub settings: CaptureSettings,
+    recording: Option<Recording>,
+}
+
+impl Capture {
+    pub fn new(settings: CaptureSettings) -> Capture {
+        Capture {
+            settings,
+            recording: None,
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Saves the picture as a png, returns where it was saved
+    pub fn screenshot(&self, picture: &RgbaImage) -> io::Result<PathBuf> {
+        let path = user_file(CAPTURES_DIR, "screenshot", "png")?;
+        scaled(picture, self.settings.scale).save(&path)?;
+
+        Ok(path)
+    }
+
+    /// Starts a recording, or finishes the current one once its frames are written.
+    /// Returns where the recording is saved
+    pub fn toggle_recording(&mut self) -> io::Result<PathBuf> {
+        if let Some(recording) = self.recording.take() {
+            return finish(recording);
+        }
+
+        let path = user_file(CAPTURES_DIR, "recording", "gif")?;
+        let (frames, pictures) = mpsc::sync_channel(QUEUED_FRAMES);
+        let (worker_path, scale, delay) = (path.clone(), self.settings.scale, self.frame_delay());
+        let worker = thread::spawn(move || encode(&worker_path, pictures, scale, delay));
+        self.recording = Some(Recording {
+            path: path.clone(),
+            frames,
+            worker,
+            skipping: 0,
+        });
+
+        Ok(path)
+    }
+
+    /// Counts an update of the game while recording,
+    /// returns whether its picture should be recorded or it is skipped
+    pub fn next_update(&mut self) -> bool {
+        let frame_skip = self.settings.frame_skip;
+        match self.recording {
+            Some(ref mut recording) if recording.skipping > 0 => {
+                recording.skipping -= 1;
+                false
+            }
+            Some(ref mut recording) => {
+                recording.skipping = frame_skip;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Adds the picture to the recording, as the next frame.
+    /// If it cannot be written the recording stops, returning the error
+    pub fn record(&mut self, picture: RgbaImage) -> io::Result<()> {
+        let sent = match self.recording {
+            Some(ref recording) => recording.frames.send(picture).is_ok(),
+            None => return Ok(()),
+        };
+        if sent {
+            return Ok(());
+        }
+
+        // The worker only hangs up after an error
+        let recording = self.recording.take().expect("The recording was just used");
+        finish(recording).map(|_| ())
+    }
+
+    /// How long each recorded frame is shown, in hundredths of a second
+    fn frame_delay(&self) -> u16 {
+        let updates = (self.settings.frame_skip + 1) as f32;
+        (updates * 100.0 / UPDATES_PER_SECOND).round() as u16
+    }
+}
+
+impl Drop for Capture {
+    // A recording still running when the game quits is finished, so that the gif is complete
+    fn drop(&mut self) {
+        if let Some(recording) = self.recording.take() {
+            if let Err(error) = finish(recording) {
+                eprintln!("Could not save the recording: {}", error);
+            }
+        }
+    }
+}
+
+/// Waits for the frames left to be written, returns where the recording was saved
+fn finish(recording: Recording) -> io::Result<PathBuf> {
+    // Closing the frames stops the worker, which writes the end of the gif
+    let Recording {
+        path,
+        frames,
+        worker,
+        ..
+    } = recording;
+    drop(frames);
+    worker.join().unwrap_or_else(|_| {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "The recording thread panicked",
+        ))
+    })?;
+
+    Ok(path)
+}
+
+/// Writes the pictures in a gif as they are received, the gif is as big as the first one
+fn encode(path: &Path, pictures: Receiver<RgbaImage>, scale: f32, delay: u16) -> io::Result<()> {
+    let mut encoder = None;
+    let mut size = (0, 0);
+    for picture in pictures {
+        let mut picture = scaled(&picture, scale);
+        if encoder.is_none() {
+            size = (picture.width() as u16, picture.height() as u16);
+            let mut created = gif::Encoder::new(File::create(path)?, size.0, size.1, &[])?;
+            created.set(gif::Repeat::Infinite)?;
+            encoder = Some(created);
+        }
+        // The window could be resized while recording
+        let (width, height) = size;
+        if picture.dimensions() != (width as u32, height as u32) {
+            picture = imageops::resize(&picture, width as u32, height as u32, FilterType::Nearest);
+        }
+
+        let mut pixels = picture.into_raw();
+        let mut frame = gif::Frame::from_rgba_speed(width, height, &mut pixels, QUANTIZE_SPEED);
+        frame.delay = delay;
+        encoder
+            .as_mut()
+            .expect("The encoder was just created")
+            .write_frame(&frame)?;
+    }
+
+    Ok(())
+}
+
+/// Resizes the picture by the scale of the settings
+fn scaled(picture: &RgbaImage, scale: f32) -> RgbaImage {
+    if scale == 1.0 {
+        return picture.clone();
+    }
+
+    let width = ((picture.width() as f32 * scale).round() as u32).max(1);
+    let height = ((picture.height() as f32 * scale).round() as u32).max(1);
+    imageops::resize(picture, width, height, FilterType::Nearest)
+}
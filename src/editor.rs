@@ -0,0 +1,485 @@
+use std::f32::consts::PI;
+use std::io;
+
+use camera::Camera;
+use game::{Object, Segment, World};
+use ggez::{Context, GameResult};
+use ggez::event::{Keycode, MouseButton};
+use ggez::graphics;
+use ggez::graphics::{Color, DrawMode, Point2, Rect, Vector2};
+use loader::{user_file, user_pack_file};
+use map::Map;
+use renderer::{ViewState, MAPS_DIR};
+use states::{MainMenu, Playing, State, Transition};
+
+// The rows of a new canvas
+const DEFAULT_HEIGHT: usize = 20;
+// The installed pack holding the segments made with the editor
+const EDITOR_PACK: &str = "editor";
+// The chance of starting a saved segment on each new row, it can be changed in the file
+const SEGMENT_RATE: f32 = 0.01;
+// How much an object turns with each press of Q or E
+const ROTATION_STEP: f32 = PI / 8.0;
+// How close to an object the mouse must be to pick it, in tiles
+const PICK_RADIUS: f32 = 0.5;
+// The color of the tiles left empty
+const EMPTY_COLOR: [f32; 3] = [0.9, 0.9, 0.92];
+
+/// What the left mouse button places on the canvas
+#[derive(Clone, Copy, PartialEq)]
+enum Brush {
+    // None empties the tile, so that a segment keeps the generated one
+    Tile(Option<usize>),
+    Object(usize),
+}
+
+/// Paints segments and maps with the mouse, on a canvas scrolled up and down.
+/// The tiles and objects are chosen from the types loaded from the packs
+pub struct Editor {
+    // The tile id of each cell, None when empty.
+    // The first row is the first one reached by the player
+    tiles: Vec<Vec<Option<usize>>>,
+    // The objects positions are relative to the bottom left of the canvas
+    objects: Vec<(usize, Object)>,
+    // Every tile type, the empty tile, then every object type
+    brushes: Vec<Brush>,
+    brush: usize,
+    camera: Camera,
+    // The button held down, it keeps painting while the mouse moves
+    held: Option<MouseButton>,
+    // The mouse position, in screen space
+    mouse: Point2,
+}
+
+impl Editor {
+    /// Opens the map being played, or an empty canvas as wide as the slope
+    pub fn new(view: &ViewState) -> Editor {
+        let world = &view.world;
+        let mut brushes: Vec<Brush> = (0..world.tile_types.len())
+            .map(|id| Brush::Tile(Some(id)))
+            .collect();
+        brushes.push(Brush::Tile(None));
+        brushes.extend((0..world.object_types.len()).map(Brush::Object));
+
+        let (tiles, objects) = match view.map {
+            Some(ref map) => (
+                map.tiles
+                    .iter()
+                    .map(|row| row.iter().map(|name| world.tile_id(name)).collect())
+                    .collect(),
+                map.objects
+                    .iter()
+                    .filter_map(|&(ref name, position, rotation)| {
+                        world.object_id(name).map(|id| {
+                            let mut object = Object::new(position);
+                            object.rotation = rotation;
+                            (id, object)
+                        })
+                    })
+                    .collect(),
+            ),
+            None => (
+                vec![vec![None; view.slope_width as usize]; DEFAULT_HEIGHT],
+                Vec::new(),
+            ),
+        };
+
+        let mut editor = Editor {
+            tiles,
+            objects,
+            brushes,
+            brush: 0,
+            camera: Camera::new(view.window_size, 1.0),
+            held: None,
+            mouse: Point2::new(0.0, 0.0),
+        };
+        editor.fit(view.window_size);
+        // Starts from the bottom row
+        let height = editor.height() as f32;
+        editor.scroll(-height);
+
+        editor
+    }
+
+    pub fn width(&self) -> usize {
+        self.tiles[0].len()
+    }
+
+    pub fn height(&self) -> usize {
+        self.tiles.len()
+    }
+
+    /// Returns the canvas as a map, every tile must be painted
+    pub fn to_map(&self, world: &World) -> io::Result<Map> {
+        let mut tiles = Vec::new();
+        for row in &self.tiles {
+            let mut names = Vec::new();
+            for tile in row {
+                let id = tile.ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "A map has no empty tiles, F fills them with the brush",
+                    )
+                })?;
+                names.push(world.tile_types[id].name.clone());
+            }
+            tiles.push(names);
+        }
+        let objects = world.named_objects(&self.objects);
+
+        Ok(Map { tiles, objects })
+    }
+
+    /// Returns the canvas as a segment, the empty tiles keep the generated ones
+    pub fn to_segment(&self, world: &World) -> Segment {
+        let tiles = self.tiles
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|tile| tile.map(|id| world.tile_types[id].name.clone()))
+                    .collect()
+            })
+            .collect();
+        let objects = world.named_objects(&self.objects);
+
+        Segment {
+            rate: SEGMENT_RATE,
+            tiles,
+            objects,
+        }
+    }
+
+    /// Saves the canvas as a segment in the pack of the editor, among the installed ones
+    fn save_segment(&self, world: &World) {
+        let segment = self.to_segment(world);
+        let path = user_pack_file(EDITOR_PACK, "segment");
+        match path.and_then(|path| segment.save(&path).map(|_| path)) {
+            Ok(path) => println!(
+                "Saved the segment in {}, it is used from the next start",
+                path.display()
+            ),
+            Err(error) => eprintln!("Could not save the segment: {}", error),
+        }
+    }
+
+    /// Saves the canvas as a map in the user data directory
+    fn save_map(&self, world: &World) {
+        let path = self.to_map(world).and_then(|map| {
+            let path = user_file(MAPS_DIR, "map", "json")?;
+            map.save(&path)?;
+            Ok(path)
+        });
+        match path {
+            Ok(path) => println!("Saved the map in {}", path.display()),
+            Err(error) => eprintln!("Could not save the map: {}", error),
+        }
+    }
+
+    /// Starts a run on the canvas, as on a map
+    fn play(&self, view: &mut ViewState) -> Transition {
+        let played = self.to_map(&view.world).and_then(|map| {
+            view.autopilot = None;
+            view.play_map(map)
+        });
+
+        match played {
+            Ok(()) => Transition::Switch(Box::new(Playing)),
+            Err(error) => {
+                eprintln!("Could not play the map: {}", error);
+                Transition::None
+            }
+        }
+    }
+
+    /// Chooses the scale so that the canvas fills the window width,
+    /// and keeps the camera inside it
+    fn fit(&mut self, window_size: (u32, u32)) {
+        let width = self.width();
+        self.camera.fit(window_size, width as u32);
+        self.camera.position.x = width as f32 / 2.0;
+        self.scroll(0.0);
+    }
+
+    /// Moves the camera up by `rows`, without showing past the bottom and top of the canvas
+    fn scroll(&mut self, rows: f32) {
+        let half_rows = self.camera.window_size.1 as f32 / self.camera.tile_size() / 2.0;
+        // The first row covers the world from -1.0 to 0.0
+        let bottom = half_rows - 1.0;
+        let top = (self.height() as f32 - 1.0 - half_rows).max(bottom);
+        self.camera.position.y = (self.camera.position.y + rows).max(bottom).min(top);
+    }
+
+    /// Changes the size of the canvas, keeping its bottom left.
+    /// The objects left outside of it are removed
+    fn resize(&mut self, width: usize, height: usize) {
+        let (width, height) = (width.max(1), height.max(1));
+        for row in &mut self.tiles {
+            row.resize(width, None);
+        }
+        self.tiles.resize(height, vec![None; width]);
+        self.objects.retain(|&(_, ref object)| {
+            object.position.x < width as f32 && object.position.y < height as f32 - 1.0
+        });
+    }
+
+    /// Returns the column and row of the tile under the mouse
+    fn hovered_tile(&self) -> Option<(usize, usize)> {
+        let position = self.camera.screen_to_world(&self.mouse);
+        // As in `World::tile_at`, each row is drawn under its index
+        let (x, y) = (position.x.floor(), position.y.floor() + 1.0);
+        if x < 0.0 || y < 0.0 || x >= self.width() as f32 || y >= self.height() as f32 {
+            None
+        } else {
+            Some((x as usize, y as usize))
+        }
+    }
+
+    /// Returns the index of the object closest to the mouse, if it is near enough
+    fn hovered_object(&self) -> Option<usize> {
+        let position = self.camera.screen_to_world(&self.mouse);
+        self.objects
+            .iter()
+            .map(|&(_, ref object)| (object.position - position).norm())
+            .enumerate()
+            .filter(|&(_, distance)| distance <= PICK_RADIUS)
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .map(|(i, _)| i)
+    }
+
+    /// Paints the tile under the mouse with the brush, or empties it with the right button
+    fn paint(&mut self, button: MouseButton) {
+        let tile = match (button, self.brushes[self.brush]) {
+            (MouseButton::Left, Brush::Tile(tile)) => tile,
+            (MouseButton::Right, _) => None,
+            _ => return,
+        };
+        if let Some((x, y)) = self.hovered_tile() {
+            self.tiles[y][x] = tile;
+        }
+    }
+
+    /// Returns the name of the brush, as shown on the screen
+    fn brush_name<'a>(&self, world: &'a World) -> &'a str {
+        match self.brushes[self.brush] {
+            Brush::Tile(Some(id)) => world.tile_types[id].name.as_str(),
+            Brush::Tile(None) => "empty",
+            Brush::Object(id) => world.object_types[id].name.as_str(),
+        }
+    }
+}
+
+impl State for Editor {
+    fn key_down(
+        &mut self,
+        view: &mut ViewState,
+        _ctx: &mut Context,
+        keycode: Keycode,
+    ) -> Transition {
+        let brushes = self.brushes.len();
+        let (width, height) = (self.width(), self.height());
+        match keycode {
+            Keycode::Escape => return Transition::Switch(Box::new(MainMenu::new())),
+            Keycode::Left => self.brush = (self.brush + brushes - 1) % brushes,
+            Keycode::Right => self.brush = (self.brush + 1) % brushes,
+            Keycode::Up => self.scroll(1.0),
+            Keycode::Down => self.scroll(-1.0),
+            Keycode::PageUp => self.resize(width, height + 1),
+            Keycode::PageDown => self.resize(width, height - 1),
+            Keycode::Equals => self.resize(width + 1, height),
+            Keycode::Minus => self.resize(width - 1, height),
+            Keycode::Q | Keycode::E => {
+                let step = if keycode == Keycode::Q {
+                    -ROTATION_STEP
+                } else {
+                    ROTATION_STEP
+                };
+                if let Some(i) = self.hovered_object() {
+                    self.objects[i].1.rotation += step;
+                }
+            }
+            Keycode::Delete | Keycode::Backspace => {
+                if let Some(i) = self.hovered_object() {
+                    self.objects.remove(i);
+                }
+            }
+            Keycode::F => {
+                if let Brush::Tile(Some(id)) = self.brushes[self.brush] {
+                    for tile in self.tiles.iter_mut().flat_map(|row| row.iter_mut()) {
+                        if tile.is_none() {
+                            *tile = Some(id);
+                        }
+                    }
+                }
+            }
+            Keycode::S => self.save_segment(&view.world),
+            Keycode::M => self.save_map(&view.world),
+            Keycode::P => return self.play(view),
+            _ => {}
+        }
+
+        Transition::None
+    }
+
+    fn mouse_down(
+        &mut self,
+        _view: &mut ViewState,
+        button: MouseButton,
+        position: Point2,
+    ) -> Transition {
+        self.mouse = position;
+
+        // The right button removes the objects before the tiles
+        if button == MouseButton::Right {
+            if let Some(i) = self.hovered_object() {
+                self.objects.remove(i);
+                return Transition::None;
+            }
+        }
+        if let (MouseButton::Left, Brush::Object(id)) = (button, self.brushes[self.brush]) {
+            if self.hovered_tile().is_some() {
+                let position = self.camera.screen_to_world(&self.mouse);
+                self.objects.push((id, Object::new(position)));
+            }
+            return Transition::None;
+        }
+
+        self.held = Some(button);
+        self.paint(button);
+        Transition::None
+    }
+
+    fn mouse_up(&mut self, _view: &mut ViewState, button: MouseButton) -> Transition {
+        if self.held == Some(button) {
+            self.held = None;
+        }
+        Transition::None
+    }
+
+    fn mouse_motion(&mut self, _view: &mut ViewState, position: Point2) -> Transition {
+        self.mouse = position;
+        if let Some(button) = self.held {
+            self.paint(button);
+        }
+        Transition::None
+    }
+
+    fn mouse_wheel(&mut self, _view: &mut ViewState, amount: i32) -> Transition {
+        self.scroll(amount as f32);
+        Transition::None
+    }
+
+    fn draw(&mut self, view: &mut ViewState, ctx: &mut Context) -> GameResult<()> {
+        // The window or the canvas could have been resized
+        self.fit(view.window_size);
+        let scale = Point2::new(self.camera.scale(), self.camera.scale());
+        let tile_size = self.camera.tile_size();
+
+        for (y, row) in self.tiles.iter().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                let position = self.camera
+                    .world_to_screen(&Vector2::new(x as f32, y as f32));
+                match *tile {
+                    Some(id) => {
+                        let texture = view.world.tile_types[id].texture;
+                        view.draw_sprite(
+                            ctx,
+                            &texture,
+                            graphics::DrawParam {
+                                dest: position,
+                                scale,
+                                ..Default::default()
+                            },
+                        )?;
+                    }
+                    None => {
+                        let color = EMPTY_COLOR;
+                        graphics::set_color(ctx, Color::new(color[0], color[1], color[2], 1.0))?;
+                        graphics::rectangle(
+                            ctx,
+                            DrawMode::Fill,
+                            Rect::new(
+                                position.x + 1.0,
+                                position.y + 1.0,
+                                tile_size - 2.0,
+                                tile_size - 2.0,
+                            ),
+                        )?;
+                        view.draw_calls += 1;
+                        // Images use the current color too
+                        graphics::set_color(ctx, graphics::WHITE)?;
+                    }
+                }
+            }
+        }
+
+        for &(id, ref object) in &self.objects {
+            let texture = view.world.object_types[id].texture;
+            view.draw_sprite(
+                ctx,
+                &texture,
+                graphics::DrawParam {
+                    dest: self.camera.world_to_screen(&object.position),
+                    scale,
+                    rotation: object.rotation,
+                    offset: Point2::new(texture.width as f32 / 32.0, texture.height as f32 / 32.0),
+                    ..Default::default()
+                },
+            )?;
+        }
+
+        // Outlines the tile under the mouse, and shows where an object would be placed
+        if let Some((x, y)) = self.hovered_tile() {
+            let position = self.camera
+                .world_to_screen(&Vector2::new(x as f32, y as f32));
+            graphics::set_color(ctx, Color::new(0.2, 0.4, 0.8, 1.0))?;
+            graphics::rectangle(
+                ctx,
+                DrawMode::Line(2.0),
+                Rect::new(position.x, position.y, tile_size, tile_size),
+            )?;
+            view.draw_calls += 1;
+
+            if let Brush::Object(id) = self.brushes[self.brush] {
+                let texture = view.world.object_types[id].texture;
+                graphics::set_color(ctx, Color::new(1.0, 1.0, 1.0, 0.5))?;
+                view.draw_sprite(
+                    ctx,
+                    &texture,
+                    graphics::DrawParam {
+                        dest: self.mouse,
+                        scale,
+                        offset: Point2::new(
+                            texture.width as f32 / 32.0,
+                            texture.height as f32 / 32.0,
+                        ),
+                        ..Default::default()
+                    },
+                )?;
+            }
+            graphics::set_color(ctx, graphics::WHITE)?;
+        }
+
+        let center = view.window_size.0 as f32 / 2.0;
+        let content = format!(
+            "Brush: {} - {}x{} tiles",
+            self.brush_name(&view.world),
+            self.width(),
+            self.height()
+        );
+        view.draw_text(ctx, &content, Point2::new(center, 15.0), graphics::BLACK)?;
+
+        let help = [
+            "Left click paints, right click empties a tile or removes an object",
+            "Left and Right choose the brush, Q and E rotate an object, F fills the empty tiles",
+            "Up, Down and the wheel scroll, Page Up and Down change the rows, - and + the columns",
+            "S saves a segment, M saves a map, P plays the map, Escape leaves",
+        ];
+        let bottom = view.window_size.1 as f32 - 20.0 * help.len() as f32;
+        for (i, line) in help.iter().enumerate() {
+            let position = Point2::new(center, bottom + i as f32 * 20.0);
+            view.draw_text(ctx, line, position, graphics::BLACK)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,384 @@
+use game::{PlayerInput, World, DEFAULT_SLOPE_WIDTH, MIN_SLOPE_HEIGHT, MIN_SLOPE_WIDTH};
+use loader::Resources;
+use simulation::new_world;
+use types::Vector2;
+
+// The simple actions of `Env::action`: each steering, going straight, tucking or braking
+pub const ACTION_COUNT: usize = 9;
+const ACTION_STEERINGS: [f32; 3] = [-1.0, 0.0, 1.0];
+
+/// What the agent sees of the world around the player
+pub struct ObservationSettings {
+    // The columns seen on each side of the player
+    pub columns: u32,
+    // The rows seen down the slope and up the slope from the player
+    pub rows_ahead: u32,
+    pub rows_behind: u32,
+    // Whether the grids and the player motion are observed
+    pub tiles: bool,
+    pub objects: bool,
+    pub player: bool,
+}
+
+impl ObservationSettings {
+    pub fn new() -> ObservationSettings {
+        ObservationSettings {
+            columns: 2,
+            rows_ahead: 6,
+            rows_behind: 1,
+            tiles: true,
+            objects: true,
+            player: true,
+        }
+    }
+
+    fn grid_size(&self) -> usize {
+        ((self.columns * 2 + 1) * (self.rows_ahead + self.rows_behind + 1)) as usize
+    }
+}
+
+/// How much each outcome of an update is rewarded
+pub struct RewardSettings {
+    // For each meter skied down the slope
+    pub distance: f32,
+    // For each update without crashing
+    pub alive: f32,
+    // Once, when the player crashes (usually negative)
+    pub crash: f32,
+    // For each update, multiplied by the meters from the center of the slope (usually negative)
+    pub center: f32,
+}
+
+impl RewardSettings {
+    pub fn new() -> RewardSettings {
+        RewardSettings {
+            distance: 1.0,
+            alive: 0.0,
+            crash: -10.0,
+            center: 0.0,
+        }
+    }
+}
+
+/// The slope and the length of the episodes
+pub struct EnvSettings {
+    pub mode: String,
+    // At least MIN_SLOPE_WIDTH, narrower slopes are widened by `Env::new`
+    pub slope_width: u32,
+    // The rows of the world, the observations cannot see above them.
+    // At least MIN_SLOPE_HEIGHT, shorter slopes are raised by `Env::new`
+    pub height: u32,
+    // The distance every episode starts from
+    pub start: u32,
+    // The most updates of an episode, and how long each one lasts
+    pub max_ticks: usize,
+    pub dt: f32,
+    pub observation: ObservationSettings,
+    pub reward: RewardSettings,
+}
+
+impl EnvSettings {
+    pub fn new() -> EnvSettings {
+        EnvSettings {
+            mode: "classic".to_owned(),
+            slope_width: DEFAULT_SLOPE_WIDTH,
+            height: 16,
+            start: 0,
+            max_ticks: 3600,
+            dt: 1.0 / 60.0,
+            observation: ObservationSettings::new(),
+            reward: RewardSettings::new(),
+        }
+    }
+}
+
+/// The surroundings of the player after an update.
+/// The grids go row by row from up the slope to down the slope, and from left to right,
+/// the player is in the cell `rows_behind` rows from the first one and `columns` from the left.
+/// The cells are the tiles of the world, the one of the player is the tile under it
+pub struct Observation {
+    // The tile id of each cell, None outside of the slope
+    pub tiles: Vec<Option<usize>>,
+    // Whether each cell is blocked by an object or is outside of the slope
+    pub blocked: Vec<bool>,
+    pub player: Option<PlayerMotion>,
+    // The number of tile types, to encode the tiles
+    tile_types: usize,
+}
+
+/// How the player is moving
+pub struct PlayerMotion {
+    pub velocity: (f32, f32),
+    pub rotation: f32,
+    pub angular_velocity: f32,
+}
+
+impl Observation {
+    /// Returns the observed values as numbers, eg: for a neural network.
+    /// Each tile becomes a one-hot vector of the tile types, all zeros outside of the slope
+    pub fn to_vec(&self) -> Vec<f32> {
+        let mut values = Vec::new();
+        for tile in &self.tiles {
+            values.extend((0..self.tile_types).map(|id| {
+                if *tile == Some(id) {
+                    1.0
+                } else {
+                    0.0
+                }
+            }));
+        }
+        values.extend(self.blocked.iter().map(|&blocked| if blocked { 1.0 } else { 0.0 }));
+        if let Some(ref player) = self.player {
+            values.push(player.velocity.0);
+            values.push(player.velocity.1);
+            values.push(player.rotation.sin());
+            values.push(player.rotation.cos());
+            values.push(player.angular_velocity);
+        }
+
+        values
+    }
+}
+
+/// The game as a learning environment: an agent chooses the input of each update,
+/// and gets back what it sees, its reward and whether the episode is over.
+/// It only needs the loaded resources, so it also builds without the `window` feature
+pub struct Env {
+    pub world: World,
+    pub settings: EnvSettings,
+    pub ticks: usize,
+    // The distance of the player at the last update
+    distance: f32,
+    done: bool,
+}
+
+impl Env {
+    pub fn new(resources: Resources, mut settings: EnvSettings) -> Env {
+        settings.slope_width = settings.slope_width.max(MIN_SLOPE_WIDTH);
+        settings.height = settings.height.max(MIN_SLOPE_HEIGHT);
+        let world = new_world(
+            resources,
+            &settings.mode,
+            settings.slope_width,
+            settings.height,
+        );
+
+        Env {
+            world,
+            settings,
+            ticks: 0,
+            distance: 0.0,
+            done: true,
+        }
+    }
+
+    /// The simple input of each action, from 0 to ACTION_COUNT
+    pub fn action(index: usize) -> PlayerInput {
+        PlayerInput {
+            steering: ACTION_STEERINGS[index % 3],
+            tucking: index / 3 == 1,
+            braking: index / 3 == 2,
+        }
+    }
+
+    /// Starts a new episode, the same seed always produces the same slope
+    pub fn reset(&mut self, seed: u64) -> Observation {
+        let settings = &self.settings;
+        self.world
+            .reset_seeded(settings.slope_width, settings.height, seed);
+        self.world.advance(settings.start);
+        self.ticks = 0;
+        self.distance = self.world.real_y + self.world.player.position.y;
+        self.done = false;
+
+        self.observe()
+    }
+
+    /// Plays an update with the input, returns the observation, the reward
+    /// and whether the episode is over.
+    /// Once over, until the next reset, the world stays as it ended and nothing is rewarded
+    pub fn step(&mut self, input: &PlayerInput) -> (Observation, f32, bool) {
+        if self.done {
+            return (self.observe(), 0.0, true);
+        }
+
+        let (_, crashed) = self.world.step(input, self.settings.dt);
+        self.ticks += 1;
+        self.done = crashed || self.ticks >= self.settings.max_ticks;
+
+        let reward_settings = &self.settings.reward;
+        let distance = self.world.real_y + self.world.player.position.y;
+        let center = self.world.width() as f32 / 2.0;
+        let mut reward = (distance - self.distance) * reward_settings.distance
+            + reward_settings.alive
+            + (self.world.player.position.x - center).abs() * reward_settings.center;
+        if crashed {
+            reward += reward_settings.crash;
+        }
+        self.distance = distance;
+
+        (self.observe(), reward, self.done)
+    }
+
+    /// Returns the number of values of the observations, once turned into numbers
+    pub fn observation_size(&self) -> usize {
+        let observation = &self.settings.observation;
+        let mut size = 0;
+        if observation.tiles {
+            size += observation.grid_size() * self.world.tile_types.len();
+        }
+        if observation.objects {
+            size += observation.grid_size();
+        }
+        if observation.player {
+            size += 5;
+        }
+
+        size
+    }
+
+    fn observe(&self) -> Observation {
+        let settings = &self.settings.observation;
+        let player = &self.world.player;
+        let columns = settings.columns as i32;
+
+        let column = player.position.x.floor() as i32;
+        let row = player.position.y.floor() as i32;
+
+        let mut tiles = Vec::new();
+        let mut blocked = Vec::new();
+        for dy in -(settings.rows_behind as i32)..settings.rows_ahead as i32 + 1 {
+            for dx in -columns..columns + 1 {
+                let (x, y) = (column + dx, row + dy);
+                // The tiles of a row are drawn one row lower, see `World::tile_at`
+                let tile = if x >= 0 && y + 1 >= 0 {
+                    self.world
+                        .tiles
+                        .get((y + 1) as usize)
+                        .and_then(|tiles| tiles.get(x as usize))
+                        .cloned()
+                } else {
+                    None
+                };
+
+                if settings.tiles {
+                    tiles.push(tile);
+                }
+                if settings.objects {
+                    let center = Vector2::new(x as f32 + 0.5, y as f32 + 0.5);
+                    blocked.push(tile.is_none() || self.object_near(center));
+                }
+            }
+        }
+
+        let motion = if settings.player {
+            Some(PlayerMotion {
+                velocity: (player.velocity.x, player.velocity.y),
+                rotation: player.rotation,
+                angular_velocity: player.angular_velocity,
+            })
+        } else {
+            None
+        };
+
+        Observation {
+            tiles,
+            blocked,
+            player: motion,
+            tile_types: self.world.tile_types.len(),
+        }
+    }
+
+    /// Whether an object hitbox reaches the cell with the center
+    fn object_near(&self, position: Vector2) -> bool {
+        self.world.objects.iter().any(|&(id, ref object)| {
+            let reach = self.world.object_types[id].hitbox / 2.0;
+            (object.position.x - position.x).abs() < reach.x + 0.5
+                && (object.position.y - position.y).abs() < reach.y + 0.5
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use loader::base_resources;
+
+    fn env(settings: EnvSettings) -> Env {
+        Env::new(base_resources(), settings)
+    }
+
+    #[test]
+    fn reset_is_deterministic() {
+        let mut environment = env(EnvSettings::new());
+        let first = environment.reset(42).to_vec();
+        environment.step(&Env::action(0));
+        assert_eq!(environment.reset(42).to_vec(), first);
+        assert_eq!(environment.ticks, 0);
+    }
+
+    #[test]
+    fn observation_size_matches_values() {
+        let mut settings = EnvSettings::new();
+        let mut environment = env(EnvSettings::new());
+        assert_eq!(
+            environment.observation_size(),
+            environment.reset(1).to_vec().len()
+        );
+
+        settings.observation.tiles = false;
+        settings.observation.columns = 3;
+        let mut environment = env(settings);
+        assert_eq!(
+            environment.observation_size(),
+            environment.reset(1).to_vec().len()
+        );
+    }
+
+    #[test]
+    fn step_rewards_distance_until_done() {
+        let mut settings = EnvSettings::new();
+        settings.mode = "flat".to_owned();
+        settings.max_ticks = 30;
+        let mut environment = env(settings);
+        environment.reset(7);
+        let start = environment.distance;
+
+        let mut total = 0.0;
+        for tick in 1..31 {
+            let (_, reward, done) = environment.step(&Env::action(1));
+            total += reward;
+            assert_eq!(done, tick == 30);
+        }
+        // Going straight down a flat slope, every meter skied is rewarded
+        let distance = environment.world.real_y + environment.world.player.position.y;
+        assert!(total > 0.0);
+        assert!((total - (distance - start)).abs() < 1e-3);
+
+        // The episode stays over until the next reset
+        let last = environment.observe().to_vec();
+        let (observation, reward, done) = environment.step(&Env::action(1));
+        assert_eq!(observation.to_vec(), last);
+        assert_eq!((reward, done), (0.0, true));
+        assert_eq!(environment.ticks, 30);
+    }
+
+    #[test]
+    fn small_slopes_are_enlarged() {
+        let mut settings = EnvSettings::new();
+        settings.slope_width = 0;
+        settings.height = 1;
+        let mut environment = env(settings);
+        environment.reset(3);
+        assert_eq!(environment.world.width(), MIN_SLOPE_WIDTH as usize);
+        assert_eq!(environment.world.height(), MIN_SLOPE_HEIGHT as usize);
+
+        // Skiing down scrolls the world without leaving it
+        for _ in 0..600 {
+            if environment.step(&Env::action(1)).2 {
+                break;
+            }
+        }
+        assert!(environment.world.tile_under_player() < environment.world.tile_types.len());
+    }
+}
@@ -1,14 +1,55 @@
-use loader::load_resources;
-use ggez::Context;
-use ggez::graphics::{Image, Vector2};
+use loader::Resources;
+use generation::{with_segments, Generator};
+use particles::ParticleSettings;
+use animation::Animations;
+use atlas::Sprite;
+use random::SeededRng;
+use types::{Color, Vector2};
+use rand;
+use std::collections::VecDeque;
+
+// The distance of each ski from the center of the player
+const SKI_DISTANCE: f32 = 0.1;
+// The minimum distance between two points of the trail
+const TRAIL_SPACING: f32 = 0.1;
+// The color of the marks left by the skies
+pub const TRAIL_COLOR: [f32; 3] = [0.6, 0.65, 0.75];
+// The friction added by the skies pushed sideways while braking
+const BRAKE_FRICTION: f32 = 1.5;
+// The player height over which the world scrolls, and by how many rows
+pub const SCROLL_Y: f32 = 6.0;
+pub const SCROLL_ROWS: u32 = 4;
+// The narrowest slope, leaving room for the player between the edges
+pub const MIN_SLOPE_WIDTH: u32 = 3;
+// The shortest slope, where the world scrolls before the player reaches its top
+pub const MIN_SLOPE_HEIGHT: u32 = SCROLL_Y as u32 + SCROLL_ROWS;
+// The number of tiles across the slope, unless configured otherwise
+pub const DEFAULT_SLOPE_WIDTH: u32 = 7;
 
 /// Holds the general information about a tile type
 /// Eg: snow, ice
 pub struct TileType {
+    // The name of the json file describing the tile
+    pub name: String,
     pub forward_friction: f32,
     pub sideway_friction: f32,
     pub distribution: f32,
-    pub texture: Image,
+    // The range of noise values covered by the tile, used by the noise generator
+    pub noise_band: Option<(f32, f32)>,
+    // How deep the marks left by the skies are, from 0.0 to 1.0
+    pub trail_depth: f32,
+    // The snow sprayed by the skies on the tile
+    pub particles: Option<ParticleSettings>,
+    pub texture: Sprite,
+    // How the tile is shown in the terminal
+    pub glyph: Option<Glyph>,
+}
+
+/// A character standing for a type in the terminal
+#[derive(Clone, Copy, Debug)]
+pub struct Glyph {
+    pub character: char,
+    pub color: Color,
 }
 
 /// The actual object present in the scene
@@ -29,27 +70,60 @@ impl Object {
 /// Holds all the information about the type of object, indetical for each instance
 /// Eg: a Rock places is general information about size and texture here
 pub struct ObjectType {
-    pub texture: Image,
+    // The name of the json file describing the object
+    pub name: String,
+    pub texture: Sprite,
+    // Eg: "idle" for a tree swaying in the wind
+    pub animations: Animations,
     pub distribution: f32,
     pub hitbox: Vector2,
+    // How the object is shown in the terminal
+    pub glyph: Option<Glyph>,
 }
 
 /// The actual player object present in the scene
+#[derive(Clone)]
 pub struct Player {
     pub position: Vector2,
     pub rotation: f32,
     pub velocity: Vector2,
     pub angular_velocity: f32,
+    // A tucked player slides faster, but steers slower
+    pub tucking: bool,
+    pub braking: bool,
 }
 
 impl Player {
-    pub fn update(&mut self, under_tile: &TileType, dt: f32) {
-        // Get the velocity along the sideways vector
+    /// Returns the velocity along the sideways vector
+    pub fn sideways_velocity(&self) -> Vector2 {
         let norm_vector = Vector2::new(-self.rotation.cos(), self.rotation.sin());
-        let sideways_velocity = norm_vector * self.velocity.dot(&norm_vector);
+        norm_vector * self.velocity.dot(&norm_vector)
+    }
+
+    /// Starts doing what the input says
+    pub fn apply_input(&mut self, input: &PlayerInput, dt: f32) {
+        self.tucking = input.tucking;
+        self.braking = input.braking;
+
+        // While turning the player slowly decreses the turning speed
+        // When switching turning directions the player steers faster
+        self.angular_velocity += (input.steering * 15.0 - self.angular_velocity * 0.2) * dt;
+    }
+
+    pub fn update(&mut self, under_tile: &TileType, dt: f32) {
+        self.velocity.y += 1.5 * dt;
+        let sideways_velocity = self.sideways_velocity();
 
         // Apply forward friction
-        self.velocity -= self.velocity * under_tile.forward_friction * dt;
+        let mut forward_friction = if self.tucking {
+            under_tile.forward_friction / 2.0
+        } else {
+            under_tile.forward_friction
+        };
+        if self.braking {
+            forward_friction += BRAKE_FRICTION;
+        }
+        self.velocity -= self.velocity * forward_friction * dt;
         // Apply sideways firction
         self.velocity -= sideways_velocity * under_tile.sideway_friction * dt;
         // Apply sideways friction to angular velocity
@@ -60,12 +134,87 @@ impl Player {
     }
 }
 
+/// What the player does during an update
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlayerInput {
+    // From -1.0 (left) to 1.0 (right)
+    pub steering: f32,
+    pub tucking: bool,
+    pub braking: bool,
+}
+
+impl PlayerInput {
+    /// Going straight, without tucking or braking
+    pub fn none() -> PlayerInput {
+        PlayerInput {
+            steering: 0.0,
+            tucking: false,
+            braking: false,
+        }
+    }
+}
+
+/// What the player crashed into
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Collision {
+    // The player went off the side of the slope
+    Edge,
+    // The id of the object type
+    Object(usize),
+}
+
+/// A point of the marks left by the skies on the snow
+pub struct TrailPoint {
+    pub position: Vector2,
+    pub rotation: f32,
+    // How visible the marks are, from 0.0 to 1.0
+    pub intensity: f32,
+}
+
+impl TrailPoint {
+    /// Returns the positions of the left and right ski
+    pub fn skies(&self) -> (Vector2, Vector2) {
+        let norm_vector = Vector2::new(-self.rotation.cos(), self.rotation.sin());
+        (
+            self.position + norm_vector * SKI_DISTANCE,
+            self.position - norm_vector * SKI_DISTANCE,
+        )
+    }
+}
+
 /// Holds all the information about the type of player, indetical for each instance
 /// In the future would hold other data about the skies
 pub struct PlayerType {
-    pub texture: Image,
+    pub texture: Sprite,
+    // "straight", "carve_left", "carve_right", "tuck" and "crashed"
+    pub animations: Animations,
 }
 
+/// A handcrafted slope section, spliced by the generator into the random rows
+#[derive(Clone)]
+pub struct Segment {
+    // The chance of starting the segment on each new row
+    pub rate: f32,
+    // The names of the tiles, None keeps the generated tile.
+    // The first row is the first one reached by the player
+    pub tiles: Vec<Vec<Option<String>>>,
+    // The object names, positions relative to the bottom left of the segment, and rotations
+    pub objects: Vec<(String, Vector2, f32)>,
+}
+
+impl Segment {
+    pub fn width(&self) -> usize {
+        self.tiles.iter().map(|row| row.len()).max().unwrap_or(0)
+    }
+
+    pub fn height(&self) -> usize {
+        self.tiles.len()
+    }
+}
+
+/// A row of tiles with the objects on it
+pub type Row = (Vec<usize>, Vec<(usize, Object)>);
+
 pub struct World {
     // The player is just a normal Object
     pub player: Player,
@@ -77,18 +226,36 @@ pub struct World {
     // The usize rappresents the object id
     pub objects: Vec<(usize, Object)>,
     pub object_types: Vec<ObjectType>,
+    pub segments: Vec<Segment>,
+    // The marks left by the player, from the oldest to the newest
+    pub trail: Vec<TrailPoint>,
+    // Taken out only while generating new rows
+    pub generator: Option<Box<Generator>>,
+    // The seed of the current run, and the rng used by the generator
+    pub seed: u64,
+    pub rng: SeededRng,
+    // The rows of a map still to be placed on top of the world, with the objects on them,
+    // before the generator continues. See `load_map`
+    pub map_rows: VecDeque<Row>,
 }
 
 impl World {
-    pub fn new(window: &mut Context) -> World {
+    pub fn new(resources: Resources) -> World {
         let player = Player {
             position: Vector2::new(0.0, 0.0),
             rotation: 0.0,
             velocity: Vector2::new(0.0, 1.0),
             angular_velocity: 0.0,
+            tucking: false,
+            braking: false,
         };
 
-        let (player_type, mut object_types, mut tile_types) = load_resources(window);
+        let player_type = resources.player_type;
+        let mut tile_types = resources.tile_types;
+        let mut object_types = resources.object_types;
+        let segments = resources.segments;
+        let generator = with_segments(resources.generator, segments.clone());
+
         // Sort from most common to most common
         tile_types.sort_unstable_by_key(|tile_type| (1.0 / tile_type.distribution) as i32);
         // Sort from most common to most uncommon
@@ -102,14 +269,35 @@ impl World {
             tile_types,
             objects: Vec::new(),
             object_types,
+            segments,
+            trail: Vec::new(),
+            generator: Some(generator),
+            seed: 0,
+            rng: SeededRng::new(0),
+            map_rows: VecDeque::new(),
         }
     }
 
+    /// Starts a new run on a random slope
     pub fn reset(&mut self, width: u32, height: u32) {
+        self.reset_seeded(width, height, rand::random());
+    }
+
+    /// Starts a new run, the same seed always produces the same slope
+    pub fn reset_seeded(&mut self, width: u32, height: u32, seed: u64) {
+        self.seed = seed;
+        self.rng = SeededRng::new(seed);
+
         self.player.position.x = width as f32 / 2.0;
         self.player.position.y = 0.0;
+        self.player.rotation = 0.0;
         self.player.velocity = Vector2::new(0.0, 0.0);
+        self.player.angular_velocity = 0.0;
+        self.player.tucking = false;
+        self.player.braking = false;
         self.real_y = 0.0;
+        self.trail.clear();
+        self.map_rows.clear();
 
         self.generate_clear(width, height);
     }
@@ -122,6 +310,20 @@ impl World {
         self.tiles.len()
     }
 
+    /// Returns the id of the tile type with the given name
+    pub fn tile_id(&self, name: &str) -> Option<usize> {
+        self.tile_types
+            .iter()
+            .position(|tile_type| tile_type.name == name)
+    }
+
+    /// Returns the id of the object type with the given name
+    pub fn object_id(&self, name: &str) -> Option<usize> {
+        self.object_types
+            .iter()
+            .position(|object_type| object_type.name == name)
+    }
+
     pub fn objects_in_radius(&self, radius: f32, point: Vector2) -> Vec<usize> {
         let mut obj_in_radius = Vec::new();
         for i in 0..self.objects.len() {
@@ -180,28 +382,151 @@ impl World {
             }
         }
 
-        let height = self.height();
-        for i in 0..scrolling {
+        for point in &mut self.trail {
+            point.position.y -= scrolling as f32;
+        }
+        self.trail.retain(|point| point.position.y >= 0.0);
+
+        // The new rows end up on top of the world, above the scrolled ones
+        for _ in 0..scrolling {
             self.tiles.remove(0);
-            self.generate_row();
-            self.generate_objects(height - i as usize);
+            self.add_row();
+        }
+    }
+
+    /// Adds a row on top of the world with its objects,
+    /// taken from the map being played or from the generator
+    pub fn add_row(&mut self) {
+        let row = self.height();
+        match self.map_rows.pop_front() {
+            Some((tiles, objects)) => {
+                self.tiles.push(tiles);
+                self.objects
+                    .extend(objects.into_iter().map(|(id, mut object)| {
+                        object.position.y += row as f32;
+                        (id, object)
+                    }));
+            }
+            None => {
+                self.generate_row();
+                self.generate_objects(row);
+            }
+        }
+    }
+
+    /// Adds rows on top of the world, or removes them, until it is `height` rows tall
+    pub fn resize_height(&mut self, height: usize) {
+        if self.height() > height {
+            self.tiles.truncate(height);
+            let top = height as f32;
+            self.objects.retain(|&(_, ref object)| object.position.y < top);
+        }
+        while self.height() < height {
+            self.add_row();
+        }
+    }
+
+    /// Moves the player `rows` further down the slope, eg: to start a run from a distance
+    pub fn advance(&mut self, rows: u32) {
+        for _ in 0..rows {
+            self.scroll(1);
+        }
+        self.player.position.y += rows as f32;
+        self.trail.clear();
+
+        // The player would start inside the objects generated around them
+        let player = self.player.position;
+        self.objects
+            .retain(|&(_, ref object)| (object.position - player).norm() > 1.5);
+    }
+
+    /// Returns the id of the tile under the player.
+    /// The closest tile is used when the player is outside of the world
+    pub fn tile_under_player(&self) -> usize {
+        self.tile_at(self.player.position)
+    }
+
+    /// Returns the id of the tile under a player standing in the position
+    pub fn tile_at(&self, position: Vector2) -> usize {
+        let rounded_pos_x = (position.x.max(0.0) as usize).min(self.width() - 1);
+        let rounded_pos_y = (position.y.max(0.0) as usize + 1).min(self.height() - 1);
+        self.tiles[rounded_pos_y][rounded_pos_x]
+    }
+
+    /// Moves the player with the input, and scrolls the world when needed.
+    /// Returns the number of rows scrolled, and whether the player crashed
+    pub fn step(&mut self, input: &PlayerInput, dt: f32) -> (u32, bool) {
+        self.player.apply_input(input, dt);
+
+        // Generate a new portion of map
+        let mut scrolling = 0;
+        if self.player.position.y > SCROLL_Y {
+            scrolling = SCROLL_ROWS;
+            self.scroll(scrolling);
         }
+
+        (scrolling, self.update(dt))
+    }
+
+    /// Moves a copy of the player like `step` would, without scrolling the world.
+    /// Returns what the player crashed into, if anything
+    pub fn predict(&self, player: &mut Player, input: &PlayerInput, dt: f32) -> Option<Collision> {
+        player.apply_input(input, dt);
+        let tile_under = self.tile_at(player.position);
+        player.update(&self.tile_types[tile_under], dt);
+
+        self.collision_at(player.position)
     }
 
     pub fn update(&mut self, dt: f32) -> bool {
-        let rounded_pos_x = self.player.position.x as usize;
-        let rounded_pos_y = self.player.position.y as usize + 1;
-        let tile_under = self.tiles[rounded_pos_y][rounded_pos_x];
+        let tile_under = self.tile_under_player();
 
-        self.player.velocity.y += 1.5 * dt;
         self.player.update(&self.tile_types[tile_under], dt);
+        self.leave_trail(tile_under);
 
         self.collided()
     }
 
+    /// Adds the player position to the trail
+    fn leave_trail(&mut self, tile_under: usize) {
+        // Points too close to the last one would not be visible
+        if let Some(last) = self.trail.last() {
+            if (last.position - self.player.position).norm() < TRAIL_SPACING {
+                return;
+            }
+        }
+
+        // The fraction of the movement which is sideways,
+        // 0.0 while carving and 1.0 while skidding
+        let speed = self.player.velocity.norm();
+        let skidding = if speed > 0.0 {
+            (self.player.sideways_velocity().norm() / speed).min(1.0)
+        } else {
+            0.0
+        };
+
+        // Skidding spreads the snow instead of cutting it
+        let intensity = self.tile_types[tile_under].trail_depth * (1.0 - skidding * 0.7);
+        self.trail.push(TrailPoint {
+            position: self.player.position,
+            rotation: self.player.rotation,
+            intensity,
+        });
+    }
+
     fn collided(&mut self) -> bool {
-        if self.player.position.x >= self.width() as f32 || self.player.position.x <= 0.0 {
-            return true;
+        self.collision().is_some()
+    }
+
+    /// Returns what the player is crashing into, if anything
+    pub fn collision(&self) -> Option<Collision> {
+        self.collision_at(self.player.position)
+    }
+
+    /// Returns what a player in the position would crash into, if anything
+    pub fn collision_at(&self, position: Vector2) -> Option<Collision> {
+        if position.x >= self.width() as f32 || position.x <= 0.0 {
+            return Some(Collision::Edge);
         }
 
         for &(ref object_id, ref object) in &self.objects {
@@ -210,11 +535,11 @@ impl World {
             let min = object.position - object_type.hitbox / 2.0;
             let max = object.position + object_type.hitbox / 2.0;
 
-            if self.player.position >= min && self.player.position <= max {
-                return true;
+            if position >= min && position <= max {
+                return Some(Collision::Object(*object_id));
             }
         }
 
-        false
+        None
     }
 }
@@ -1,39 +1,133 @@
+use std::mem;
+
 use game::*;
-use ggez::graphics::Vector2;
+use types::Vector2;
+use noise::Perlin;
 use rand;
 use rand::Rng;
+use random::SeededRng;
+use serde_json::Value;
 
-impl World {
-    pub fn generate_clear(&mut self, width: u32, height: u32) {
-        self.tiles.clear();
-        self.objects.clear();
+/// An algorithm able to produce the tiles and objects of the world.
+/// The world holds one and asks it for new content every time it scrolls.
+/// All the random choices are taken from the world `rng`,
+/// so that the same seed always produces the same slope
+pub trait Generator {
+    /// Returns the starting area of the world, called every time the world is reset.
+    /// By default the area is completely covered by the most common tile
+    fn reset(&mut self, width: u32, height: u32, _rng: &mut SeededRng) -> Vec<Vec<usize>> {
+        clear_area(width, height)
+    }
+
+    /// Returns a new row of tile ids to be placed on top of the world
+    fn generate_row(&mut self, world: &World, rng: &mut SeededRng) -> Vec<usize>;
+
+    /// Returns the objects to be placed on the row at the given height.
+    /// By default they are chosen with the cellular algorithm
+    fn generate_objects(
+        &mut self,
+        world: &World,
+        height: usize,
+        rng: &mut SeededRng,
+    ) -> Vec<(usize, Object)> {
+        cellular_objects(world, height, rng)
+    }
 
-        for _ in 0..height {
-            let mut row = Vec::new();
-            for _ in 0..width {
-                row.push(0);
+    /// Returns what the generator remembers of the rows already generated,
+    /// so that a saved game continues with the same rows.
+    /// Everything set up by `reset` is created again from the seed
+    fn state(&self) -> Value {
+        Value::Null
+    }
+
+    /// Continues from the state, after being reset with the seed of the saved game
+    fn restore(&mut self, _state: &Value) {}
+}
+
+/// Returns the generator with the given name and its default settings
+pub fn generator_from_name(name: &str) -> Option<Box<Generator>> {
+    match name {
+        "cellular" => Some(Box::new(CellularGenerator)),
+        "noise" => Some(Box::new(NoiseGenerator::new(0.15, 3, 0.5))),
+        "flat" => Some(Box::new(FlatGenerator)),
+        _ => None,
+    }
+}
+
+/// An area completely covered by the most common tile
+/// (the first in the tiles vector)
+pub fn clear_area(width: u32, height: u32) -> Vec<Vec<usize>> {
+    let mut tiles = Vec::new();
+    for _ in 0..height {
+        let mut row = Vec::new();
+        for _ in 0..width {
+            row.push(0);
+        }
+        tiles.push(row);
+    }
+
+    tiles
+}
+
+/// Chooses the objects of a row with the cellular algorithm.
+/// The chance of an object depends on how many objects are already close to it
+pub fn cellular_objects(world: &World, height: usize, rng: &mut SeededRng) -> Vec<(usize, Object)> {
+    let mut objects: Vec<(usize, Object)> = Vec::new();
+
+    for x in 0..world.width() {
+        for _ in 0..world.object_types.len() {
+            let id = rng.gen_range(0, world.object_types.len());
+            let mut chance = 1.0 / world.object_types[id].distribution;
+
+            // x and y are the bottom left coordinates of the tile
+            // Adding 0.5 places it in the center of the tile
+            let position = Vector2::new(x as f32 + 0.5, height as f32 + 0.5);
+            // The objects already placed on this row count too
+            let objects_close = world.objects_in_radius(3.0, position).len()
+                + objects
+                    .iter()
+                    .filter(|&&(_, ref object)| (object.position - position).norm() <= 3.0)
+                    .count();
+
+            if objects_close >= 1 {
+                chance = (chance / 2.0).ceil();
+            }
+            if objects_close >= 2 {
+                chance *= 6.0;
+            }
+
+            if rng.gen_weighted_bool(chance as u32) {
+                let object_id = rng.gen_range(0, world.object_types.len());
+                objects.push((object_id, Object::new(position)));
+                break;
             }
-            self.tiles.push(row);
         }
     }
 
-    pub fn generate_row(&mut self) {
-        let mut rng = rand::thread_rng();
+    objects
+}
+
+/// The default generator, cellular automata inspired.
+/// It modifies the chance of a tile starting from its distribution
+/// depending on the identical tiles already present around it
+pub struct CellularGenerator;
 
+impl Generator for CellularGenerator {
+    fn generate_row(&mut self, world: &World, rng: &mut SeededRng) -> Vec<usize> {
         let mut row = Vec::new();
-        let y = self.height();
-        for x in 0..self.width() {
+        let y = world.height();
+        for x in 0..world.width() {
             // If no tile has been choosen fall back on the most common one
             // (the first in the tiles vector)
             let mut choosen_tile = 0;
 
             // It tries tile_types.len() times to choose a random tile
-            for _ in 0..self.tile_types.len() {
-                let id = rng.gen_range(0, self.tile_types.len());
-                let mut chance = 1.0 / self.tile_types[id].distribution;
+            for _ in 0..world.tile_types.len() {
+                let id = rng.gen_range(0, world.tile_types.len());
+                let mut chance = 1.0 / world.tile_types[id].distribution;
 
                 // The number of tiles of the same type
-                let mut neighbors = self.get_close_tiles(x, y);
+                let mut neighbors = world.get_close_tiles(x, y);
                 neighbors.retain(|&tile_id| tile_id == id);
                 let similar = neighbors.len();
                 if similar >= 1 {
@@ -52,35 +146,300 @@ impl World {
             row.push(choosen_tile);
         }
 
-        self.tiles.push(row);
+        row
     }
+}
 
-    pub fn generate_objects(&mut self, height: usize) {
-        let mut rng = rand::thread_rng();
+/// A generator sampling coherent noise, which produces large smooth areas.
+/// Each tile type declares the band of noise values it covers
+pub struct NoiseGenerator {
+    noise: Perlin,
+    // The number of rows generated since the last reset
+    row: u64,
+    // How much the noise is stretched over the tiles
+    scale: f64,
+    octaves: u32,
+    persistence: f64,
+}
 
-        for x in 0..self.width() {
-            for _ in 0..self.object_types.len() {
-                let id = rng.gen_range(0, self.object_types.len());
-                let mut chance = 1.0 / self.object_types[id].distribution;
+impl NoiseGenerator {
+    pub fn new(scale: f64, octaves: u32, persistence: f64) -> NoiseGenerator {
+        NoiseGenerator {
+            noise: Perlin::new(&mut rand::thread_rng()),
+            row: 0,
+            scale,
+            octaves,
+            persistence,
+        }
+    }
+}
 
-                // x and y are the bottom left coordinates of the tile
-                // Adding 0.5 places it in the center of the tile
-                let position = Vector2::new(x as f32 + 0.5, height as f32 + 0.5);
-                let objects_close = self.objects_in_radius(3.0, position).len();
+impl Generator for NoiseGenerator {
+    fn reset(&mut self, width: u32, height: u32, rng: &mut SeededRng) -> Vec<Vec<usize>> {
+        // Every run gets a different slope
+        self.noise = Perlin::new(rng);
+        self.row = 0;
 
-                if objects_close >= 1 {
-                    chance = (chance / 2.0).ceil();
+        clear_area(width, height)
+    }
+
+    fn generate_row(&mut self, world: &World, _rng: &mut SeededRng) -> Vec<usize> {
+        let mut row = Vec::new();
+        let y = self.row as f64 * self.scale;
+        for x in 0..world.width() {
+            let value = self.noise
+                .octaves(x as f64 * self.scale, y, self.octaves, self.persistence)
+                as f32;
+
+            // If no band contains the value fall back on the most common tile
+            // (the first in the tiles vector)
+            let mut choosen_tile = 0;
+            for (id, tile_type) in world.tile_types.iter().enumerate() {
+                if let Some((min, max)) = tile_type.noise_band {
+                    if value >= min && value < max {
+                        choosen_tile = id;
+                        break;
+                    }
+                }
+            }
+
+            row.push(choosen_tile);
+        }
+
+        self.row += 1;
+        row
+    }
+
+    fn state(&self) -> Value {
+        json!({ "row": self.row })
+    }
+
+    fn restore(&mut self, state: &Value) {
+        self.row = state["row"].as_u64().unwrap_or(0);
+    }
+}
+
+/// A generator producing only the most common tile and no objects.
+/// Useful as a predictable fixture
+pub struct FlatGenerator;
+
+impl Generator for FlatGenerator {
+    fn generate_row(&mut self, world: &World, _rng: &mut SeededRng) -> Vec<usize> {
+        vec![0; world.width()]
+    }
+
+    fn generate_objects(
+        &mut self,
+        _world: &World,
+        _height: usize,
+        _rng: &mut SeededRng,
+    ) -> Vec<(usize, Object)> {
+        Vec::new()
+    }
+}
+
+/// Wraps the generator so that it splices the segments in its rows.
+/// Without segments the generator is returned as it is
+pub fn with_segments(generator: Box<Generator>, segments: Vec<Segment>) -> Box<Generator> {
+    if segments.is_empty() {
+        generator
+    } else {
+        Box::new(SegmentGenerator::new(generator, segments))
+    }
+}
+
+/// The segment currently being spliced in the world
+struct ActiveSegment {
+    id: usize,
+    // The segment row placed by the last generate_row
+    row: usize,
+    // The column of the world where the segment starts
+    offset: usize,
+}
+
+/// A generator splicing handcrafted segments in the rows of another generator.
+/// The tiles left empty by a segment, and the columns around it,
+/// keep the ones produced by the base generator
+pub struct SegmentGenerator {
+    base: Box<Generator>,
+    segments: Vec<Segment>,
+    active: Option<ActiveSegment>,
+}
+
+impl SegmentGenerator {
+    pub fn new(base: Box<Generator>, segments: Vec<Segment>) -> SegmentGenerator {
+        SegmentGenerator {
+            base,
+            segments,
+            active: None,
+        }
+    }
+
+    /// Moves to the next segment row, or starts a new segment
+    fn advance(&mut self, world_width: usize, rng: &mut SeededRng) {
+        // Continue the active segment until its rows are finished
+        if let Some(ref mut active) = self.active {
+            active.row += 1;
+            if active.row < self.segments[active.id].height() {
+                return;
+            }
+        }
+        self.active = None;
+
+        for (id, segment) in self.segments.iter().enumerate() {
+            // Segments wider than the world can never be placed
+            if segment.width() > world_width || segment.height() == 0 {
+                continue;
+            }
+
+            if rng.next_f32() < segment.rate {
+                let offset = rng.gen_range(0, world_width - segment.width() + 1);
+                self.active = Some(ActiveSegment { id, row: 0, offset });
+                break;
+            }
+        }
+    }
+}
+
+impl Generator for SegmentGenerator {
+    fn reset(&mut self, width: u32, height: u32, rng: &mut SeededRng) -> Vec<Vec<usize>> {
+        self.active = None;
+        self.base.reset(width, height, rng)
+    }
+
+    fn generate_row(&mut self, world: &World, rng: &mut SeededRng) -> Vec<usize> {
+        let mut row = self.base.generate_row(world, rng);
+        self.advance(world.width(), rng);
+
+        if let Some(ref active) = self.active {
+            let segment_row = &self.segments[active.id].tiles[active.row];
+            for (x, tile) in segment_row.iter().enumerate() {
+                if let Some(ref name) = *tile {
+                    row[active.offset + x] = world.tile_id(name).expect("Unknown segment tile");
                 }
-                if objects_close >= 2 {
-                    chance *= 6.0;
+            }
+        }
+
+        row
+    }
+
+    fn generate_objects(
+        &mut self,
+        world: &World,
+        height: usize,
+        rng: &mut SeededRng,
+    ) -> Vec<(usize, Object)> {
+        let mut objects = self.base.generate_objects(world, height, rng);
+
+        if let Some(ref active) = self.active {
+            let segment = &self.segments[active.id];
+
+            // The segment tiles are kept free from the generated objects
+            let segment_row = &segment.tiles[active.row];
+            objects.retain(|&(_, ref object)| {
+                let x = object.position.x as usize;
+                if x < active.offset || x >= active.offset + segment_row.len() {
+                    return true;
                 }
+                segment_row[x - active.offset].is_none()
+            });
 
-                if rng.gen_weighted_bool(chance as u32) {
-                    let object_id = rng.gen_range(0, self.object_types.len());
-                    self.objects.push((object_id, Object::new(position)));
-                    break;
+            for &(ref name, ref position, rotation) in &segment.objects {
+                let row = position.y as usize;
+                if row == active.row {
+                    let object_id = world.object_id(name).expect("Unknown segment object");
+                    let position = Vector2::new(
+                        position.x + active.offset as f32,
+                        position.y - row as f32 + height as f32,
+                    );
+                    let mut object = Object::new(position);
+                    object.rotation = rotation;
+                    objects.push((object_id, object));
                 }
             }
         }
+
+        objects
+    }
+
+    fn state(&self) -> Value {
+        let active = self.active
+            .as_ref()
+            .map(|active| json!([active.id, active.row, active.offset]));
+        json!({
+            "active": active,
+            "base": self.base.state(),
+        })
+    }
+
+    fn restore(&mut self, state: &Value) {
+        self.active = state["active"].as_array().and_then(|active| {
+            let id = active.get(0)?.as_u64()? as usize;
+            let row = active.get(1)?.as_u64()? as usize;
+            let offset = active.get(2)?.as_u64()? as usize;
+            // The segments could have changed since the game was saved
+            if id < self.segments.len() && row < self.segments[id].height() {
+                Some(ActiveSegment { id, row, offset })
+            } else {
+                None
+            }
+        });
+        self.base.restore(&state["base"]);
+    }
+}
+
+impl World {
+    /// Replaces the generator and resets the world with the new one.
+    /// The world segments are spliced in the new generator too
+    pub fn set_generator(&mut self, generator: Box<Generator>) {
+        let width = self.width() as u32;
+        let height = self.height() as u32;
+
+        self.generator = Some(with_segments(generator, self.segments.clone()));
+        self.reset(width, height);
+    }
+
+    /// Replaces the generator as it is, without splicing the segments,
+    /// and resets the world with it. Returns the previous generator
+    pub fn replace_generator(&mut self, generator: Box<Generator>) -> Box<Generator> {
+        let width = self.width() as u32;
+        let height = self.height() as u32;
+
+        let previous = mem::replace(&mut self.generator, Some(generator));
+        self.reset(width, height);
+
+        previous.expect("The world has no generator")
+    }
+
+    pub fn generate_clear(&mut self, width: u32, height: u32) {
+        self.objects.clear();
+        self.tiles = self.with_generator(|generator, _, rng| generator.reset(width, height, rng));
+    }
+
+    pub fn generate_row(&mut self) {
+        let row = self.with_generator(|generator, world, rng| generator.generate_row(world, rng));
+        self.tiles.push(row);
+    }
+
+    pub fn generate_objects(&mut self, height: usize) {
+        let objects = self.with_generator(|generator, world, rng| {
+            generator.generate_objects(world, height, rng)
+        });
+        self.objects.extend(objects);
+    }
+
+    /// Runs the function with the generator and the rng taken out of the world,
+    /// so that the generator can read the world while it is modified
+    fn with_generator<T, F>(&mut self, function: F) -> T
+    where
+        F: FnOnce(&mut Generator, &World, &mut SeededRng) -> T,
+    {
+        let mut generator = self.generator.take().expect("The world has no generator");
+        let mut rng = self.rng.clone();
+        let result = function(&mut *generator, self, &mut rng);
+        self.generator = Some(generator);
+        self.rng = rng;
+
+        result
     }
 }
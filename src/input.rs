@@ -0,0 +1,395 @@
+use serde_json;
+use serde_json::Value;
+
+use std::io::prelude::*;
+use ggez::{Context, GameResult};
+use ggez::event::{Axis, Button, Keycode};
+
+// The file holding the settings, in the user config directory
+const SETTINGS_PATH: &str = "/settings.json";
+// How far a trigger must be pressed to tuck or brake, from 0.0 to 1.0
+const TRIGGER_THRESHOLD: f32 = 0.3;
+
+/// Something the player can do, bound to a key
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    SteerLeft,
+    SteerRight,
+    Tuck,
+    Brake,
+    Pause,
+    Restart,
+    Screenshot,
+    Record,
+    ExportMap,
+}
+
+// Every action, in the order shown by the controls screen
+pub const ACTIONS: [Action; 9] = [
+    Action::SteerLeft,
+    Action::SteerRight,
+    Action::Tuck,
+    Action::Brake,
+    Action::Pause,
+    Action::Restart,
+    Action::Screenshot,
+    Action::Record,
+    Action::ExportMap,
+];
+
+impl Action {
+    /// The name used in the settings file
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Action::SteerLeft => "steer_left",
+            Action::SteerRight => "steer_right",
+            Action::Tuck => "tuck",
+            Action::Brake => "brake",
+            Action::Pause => "pause",
+            Action::Restart => "restart",
+            Action::Screenshot => "screenshot",
+            Action::Record => "record",
+            Action::ExportMap => "export_map",
+        }
+    }
+
+    /// The name shown to the player
+    pub fn label(&self) -> &'static str {
+        match *self {
+            Action::SteerLeft => "Steer left",
+            Action::SteerRight => "Steer right",
+            Action::Tuck => "Tuck",
+            Action::Brake => "Brake",
+            Action::Pause => "Pause",
+            Action::Restart => "Restart",
+            Action::Screenshot => "Screenshot",
+            Action::Record => "Record",
+            Action::ExportMap => "Export map",
+        }
+    }
+}
+
+/// The key bound to each action and the gamepad settings, saved in the settings file
+pub struct InputSettings {
+    bindings: Vec<(Action, Keycode)>,
+    // The part of the stick movement ignored around the center, from 0.0 to 1.0
+    pub deadzone: f32,
+    // How much the stick steers, 1.0 steers fully when the stick is fully moved
+    pub sensitivity: f32,
+}
+
+impl InputSettings {
+    pub fn new() -> InputSettings {
+        InputSettings {
+            bindings: vec![
+                (Action::SteerLeft, Keycode::Left),
+                (Action::SteerRight, Keycode::Right),
+                (Action::Tuck, Keycode::Down),
+                (Action::Brake, Keycode::Up),
+                (Action::Pause, Keycode::Escape),
+                (Action::Restart, Keycode::R),
+                (Action::Screenshot, Keycode::F12),
+                (Action::Record, Keycode::F11),
+                (Action::ExportMap, Keycode::F9),
+            ],
+            deadzone: 0.2,
+            sensitivity: 1.0,
+        }
+    }
+
+    /// Reads the settings file, the actions missing from it keep their default key
+    pub fn load(ctx: &mut Context) -> InputSettings {
+        let mut settings = InputSettings::new();
+
+        let mut json_src = String::new();
+        let read = ctx.filesystem
+            .open(SETTINGS_PATH)
+            .and_then(|mut file| Ok(file.read_to_string(&mut json_src)?));
+        if read.is_err() {
+            // The first time the game is played there are no settings yet
+            return settings;
+        }
+
+        let json: Value = match serde_json::from_str(&json_src) {
+            Ok(json) => json,
+            Err(error) => {
+                eprintln!("Ignoring the invalid settings file: {}", error);
+                return settings;
+            }
+        };
+        for action in &ACTIONS {
+            if let Some(name) = json["bindings"][action.name()].as_str() {
+                match Keycode::from_name(name) {
+                    Some(key) => settings.bind(*action, key),
+                    None => eprintln!("Unknown key {} for {}", name, action.name()),
+                }
+            }
+        }
+        if let Some(deadzone) = json["gamepad"]["deadzone"].as_f64() {
+            settings.deadzone = (deadzone as f32).max(0.0).min(0.9);
+        }
+        if let Some(sensitivity) = json["gamepad"]["sensitivity"].as_f64() {
+            settings.sensitivity = sensitivity as f32;
+        }
+
+        settings
+    }
+
+    /// Writes the settings file
+    pub fn save(&self, ctx: &mut Context) -> GameResult<()> {
+        let mut bindings = serde_json::Map::new();
+        for &(action, key) in &self.bindings {
+            bindings.insert(action.name().to_owned(), Value::String(key.name()));
+        }
+        let json = json!({
+            "bindings": bindings,
+            "gamepad": {
+                "deadzone": self.deadzone,
+                "sensitivity": self.sensitivity,
+            },
+        });
+
+        let mut file = ctx.filesystem.create(SETTINGS_PATH)?;
+        file.write_all(serde_json::to_string_pretty(&json).unwrap().as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Returns the key bound to the action
+    pub fn key(&self, action: Action) -> Keycode {
+        self.bindings
+            .iter()
+            .find(|&&(other, _)| other == action)
+            .map(|&(_, key)| key)
+            .expect("Every action has a key")
+    }
+
+    /// Returns the action bound to the key, if any
+    pub fn action(&self, key: Keycode) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|&&(_, other)| other == key)
+            .map(|&(action, _)| action)
+    }
+
+    /// Binds the key to the action.
+    /// An action already using the key gets the previous key of this one,
+    /// so that no key triggers two actions
+    pub fn bind(&mut self, action: Action, key: Keycode) {
+        let previous = self.key(action);
+        for binding in &mut self.bindings {
+            if binding.0 == action {
+                binding.1 = key;
+            } else if binding.1 == key {
+                binding.1 = previous;
+            }
+        }
+    }
+}
+
+/// The keys and gamepad buttons currently pressed, and the position of the sticks.
+/// It is fed with the window events, and tells the actions the player is doing
+pub struct InputState {
+    pub pressed_keys: Vec<Keycode>,
+    pub pressed_buttons: Vec<Button>,
+    // The horizontal position of the left stick, from -1.0 to 1.0
+    pub stick_x: f32,
+    // How much each trigger is pressed, from 0.0 to 1.0
+    pub left_trigger: f32,
+    pub right_trigger: f32,
+}
+
+impl InputState {
+    pub fn new() -> InputState {
+        InputState {
+            pressed_keys: Vec::new(),
+            pressed_buttons: Vec::new(),
+            stick_x: 0.0,
+            left_trigger: 0.0,
+            right_trigger: 0.0,
+        }
+    }
+
+    pub fn key_down(&mut self, key: Keycode) {
+        if !self.pressed_keys.contains(&key) {
+            self.pressed_keys.push(key);
+        }
+    }
+
+    pub fn key_up(&mut self, key: Keycode) {
+        self.pressed_keys.retain(|&other| other != key);
+    }
+
+    pub fn button_down(&mut self, button: Button) {
+        if !self.pressed_buttons.contains(&button) {
+            self.pressed_buttons.push(button);
+        }
+    }
+
+    pub fn button_up(&mut self, button: Button) {
+        self.pressed_buttons.retain(|&other| other != button);
+    }
+
+    /// Moves a stick or a trigger, `value` is the raw value sent by the gamepad
+    pub fn axis(&mut self, axis: Axis, value: i16) {
+        let value = (f32::from(value) / 32767.0).max(-1.0);
+        match axis {
+            Axis::LeftX => self.stick_x = value,
+            Axis::TriggerLeft => self.left_trigger = value.max(0.0),
+            Axis::TriggerRight => self.right_trigger = value.max(0.0),
+            _ => {}
+        }
+    }
+
+    /// Forgets everything pressed, eg: when the window loses the focus
+    pub fn clear(&mut self) {
+        *self = InputState::new();
+    }
+
+    /// How much the player steers, from -1.0 (left) to 1.0 (right).
+    /// The keys and the directional pad steer fully, the stick proportionally
+    pub fn steering(&self, settings: &InputSettings) -> f32 {
+        let mut steering = 0.0;
+        if self.is_active(Action::SteerRight, settings) {
+            steering += 1.0;
+        }
+        if self.is_active(Action::SteerLeft, settings) {
+            steering -= 1.0;
+        }
+
+        // The stick movement out of the deadzone is stretched over the whole range
+        let distance = self.stick_x.abs();
+        if distance > settings.deadzone {
+            let amount = (distance - settings.deadzone) / (1.0 - settings.deadzone);
+            steering += self.stick_x.signum() * amount * settings.sensitivity;
+        }
+
+        steering.max(-1.0).min(1.0)
+    }
+
+    /// Whether the player is doing the action, with its key or with the gamepad
+    pub fn is_active(&self, action: Action, settings: &InputSettings) -> bool {
+        if self.pressed_keys.contains(&settings.key(action)) {
+            return true;
+        }
+
+        let button = match action {
+            Action::SteerLeft => Button::DPadLeft,
+            Action::SteerRight => Button::DPadRight,
+            Action::Tuck => return self.right_trigger > TRIGGER_THRESHOLD,
+            Action::Brake => return self.left_trigger > TRIGGER_THRESHOLD,
+            Action::Pause => Button::Start,
+            Action::Restart => Button::Back,
+            // Only the keys take pictures and export the maps
+            Action::Screenshot | Action::Record | Action::ExportMap => return false,
+        };
+        self.pressed_buttons.contains(&button)
+    }
+}
+
+/// The key a gamepad button stands for in the menus, so that they can be navigated with it
+pub fn button_key(button: Button, settings: &InputSettings) -> Option<Keycode> {
+    match button {
+        Button::DPadUp => Some(Keycode::Up),
+        Button::DPadDown => Some(Keycode::Down),
+        Button::DPadLeft => Some(Keycode::Left),
+        Button::DPadRight => Some(Keycode::Right),
+        Button::A => Some(Keycode::Return),
+        Button::B => Some(Keycode::Escape),
+        Button::Start => Some(settings.key(Action::Pause)),
+        Button::Back => Some(settings.key(Action::Restart)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stick_ignores_deadzone() {
+        let settings = InputSettings::new();
+        let mut state = InputState::new();
+        // Just inside the deadzone of 0.2
+        state.axis(Axis::LeftX, 6000);
+        assert_eq!(state.steering(&settings), 0.0);
+
+        // Just out of it the steering starts from zero
+        state.axis(Axis::LeftX, -7000);
+        let steering = state.steering(&settings);
+        assert!(steering < 0.0 && steering > -0.05);
+    }
+
+    #[test]
+    fn stick_scales_with_sensitivity() {
+        let mut settings = InputSettings::new();
+        let mut state = InputState::new();
+        state.axis(Axis::LeftX, 19660);
+        let steering = state.steering(&settings);
+        assert!((steering - 0.5).abs() < 0.01);
+
+        settings.sensitivity = 1.5;
+        assert!((state.steering(&settings) - 0.75).abs() < 0.01);
+    }
+
+    #[test]
+    fn steering_is_clamped() {
+        let mut settings = InputSettings::new();
+        settings.sensitivity = 3.0;
+        let mut state = InputState::new();
+        state.axis(Axis::LeftX, i16::min_value());
+        assert_eq!(state.stick_x, -1.0);
+        assert_eq!(state.steering(&settings), -1.0);
+
+        // The key and the stick together still steer fully
+        state.axis(Axis::LeftX, i16::max_value());
+        state.key_down(Keycode::Right);
+        assert_eq!(state.steering(&settings), 1.0);
+    }
+
+    #[test]
+    fn triggers_tuck_and_brake_past_threshold() {
+        let settings = InputSettings::new();
+        let mut state = InputState::new();
+        state.axis(Axis::TriggerRight, 9000);
+        state.axis(Axis::TriggerLeft, 9000);
+        assert!(!state.is_active(Action::Tuck, &settings));
+        assert!(!state.is_active(Action::Brake, &settings));
+
+        state.axis(Axis::TriggerRight, 11000);
+        assert!(state.is_active(Action::Tuck, &settings));
+        assert!(!state.is_active(Action::Brake, &settings));
+
+        state.axis(Axis::TriggerLeft, 32767);
+        assert!(state.is_active(Action::Brake, &settings));
+    }
+
+    #[test]
+    fn buttons_and_keys_do_actions() {
+        let settings = InputSettings::new();
+        let mut state = InputState::new();
+        state.button_down(Button::DPadLeft);
+        assert_eq!(state.steering(&settings), -1.0);
+        state.button_up(Button::DPadLeft);
+        assert_eq!(state.steering(&settings), 0.0);
+
+        state.key_down(Keycode::Down);
+        assert!(state.is_active(Action::Tuck, &settings));
+        state.key_up(Keycode::Down);
+        assert!(!state.is_active(Action::Tuck, &settings));
+    }
+
+    #[test]
+    fn buttons_follow_the_bindings_in_menus() {
+        let mut settings = InputSettings::new();
+        assert_eq!(button_key(Button::A, &settings), Some(Keycode::Return));
+        assert_eq!(button_key(Button::B, &settings), Some(Keycode::Escape));
+        assert_eq!(button_key(Button::DPadUp, &settings), Some(Keycode::Up));
+        assert_eq!(button_key(Button::X, &settings), None);
+
+        settings.bind(Action::Pause, Keycode::P);
+        settings.bind(Action::Restart, Keycode::Backspace);
+        assert_eq!(button_key(Button::Start, &settings), Some(Keycode::P));
+        assert_eq!(button_key(Button::Back, &settings), Some(Keycode::Backspace));
+    }
+}
@@ -1,8 +1,39 @@
+extern crate app_dirs;
+extern crate crossterm;
+#[cfg(feature = "window")]
 extern crate ggez;
+extern crate gif;
+extern crate image;
+extern crate nalgebra;
 extern crate rand;
+#[macro_use]
 extern crate serde_json;
 
+pub mod animation;
+pub mod atlas;
+pub mod autopilot;
+pub mod camera;
+pub mod capture;
+#[cfg(feature = "window")]
+pub mod editor;
+pub mod env;
 pub mod game;
+#[cfg(feature = "window")]
+pub mod input;
 pub mod loader;
+pub mod map;
+#[cfg(feature = "window")]
 pub mod renderer;
 pub mod generation;
+pub mod noise;
+pub mod particles;
+pub mod random;
+pub mod replay;
+pub mod savegame;
+pub mod simulation;
+pub mod snapshot;
+#[cfg(feature = "window")]
+pub mod states;
+pub mod terminal;
+pub mod types;
+pub mod validate;
@@ -1,96 +1,791 @@
 use serde_json;
 use serde_json::Value;
+use image;
+use image::RgbaImage;
+use app_dirs::{get_app_dir, AppDataType, AppInfo};
 
+use std::fs::{self, File};
+use std::io;
 use std::io::prelude::*;
-use ggez::graphics::{FilterMode, Image, Vector2};
-use ggez::Context;
-use game::{ObjectType, PlayerType, TileType};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use types::{Color, Vector2};
+use animation::{Animation, Animations, LoopMode};
+use atlas::{Atlas, AtlasBuilder, Sprite};
+use game::{Glyph, ObjectType, PlayerType, Segment, TileType};
+use generation::{generator_from_name, CellularGenerator, Generator, NoiseGenerator};
+use particles::ParticleSettings;
 
-const ASSETS_PATH: &str = "/config/";
+// The directories of an asset pack holding the description files and the textures
+pub const CONFIG_DIR: &str = "config";
+pub const TEXTURES_DIR: &str = "textures";
+// The manifest found in the root of every asset pack
+pub const MANIFEST_FILE: &str = "pack.json";
+// The directory in the user data dir holding the packs installed by the player
+const USER_PACKS_DIR: &str = "packs";
+// The same app info used by ggez, so that the packs sit next to the other user data
+const APP_INFO: AppInfo = AppInfo {
+    name: "Skii",
+    author: "Piripant",
+};
+
+/// A directory of description files and textures, described by its manifest
+pub struct Pack {
+    pub name: String,
+    pub version: String,
+    pub author: String,
+    pub directory: PathBuf,
+}
+
+impl Pack {
+    /// Reads the manifest of the pack in the directory
+    pub fn load(directory: &Path) -> io::Result<Pack> {
+        let mut json_src = String::new();
+        File::open(directory.join(MANIFEST_FILE))?.read_to_string(&mut json_src)?;
+        let json: Value = serde_json::from_str(&json_src)?;
+
+        let field = |name: &str| {
+            json[name]
+                .as_str()
+                .map(|value| value.to_owned())
+                .ok_or_else(|| invalid(format!("The manifest has no valid {}", name)))
+        };
+        Ok(Pack {
+            name: field("name")?,
+            version: field("version")?,
+            author: field("author")?,
+            directory: directory.to_owned(),
+        })
+    }
+}
+
+/// Returns the directory holding the packs installed by the player
+pub fn user_packs_dir() -> Option<PathBuf> {
+    get_app_dir(AppDataType::UserData, &APP_INFO, USER_PACKS_DIR).ok()
+}
+
+/// Returns a new file in the directory of the user data dir, named after the current time.
+/// The directory is created when missing
+pub fn user_file(directory: &str, name: &str, extension: &str) -> io::Result<PathBuf> {
+    let directory = get_app_dir(AppDataType::UserData, &APP_INFO, directory)
+        .map_err(|error| io::Error::new(io::ErrorKind::Other, error.to_string()))?;
+    fs::create_dir_all(&directory)?;
+
+    Ok(directory.join(format!("{}-{}.{}", name, timestamp(), extension)))
+}
+
+/// Returns a new description file in the installed pack, named after the current time.
+/// The pack is created when missing, so that the file is loaded with the other packs
+pub fn user_pack_file(pack: &str, name: &str) -> io::Result<PathBuf> {
+    let directory = user_packs_dir()
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "The user data directory could not be found",
+            )
+        })?
+        .join(pack);
+    fs::create_dir_all(directory.join(CONFIG_DIR))?;
+
+    let manifest = directory.join(MANIFEST_FILE);
+    if !manifest.is_file() {
+        let json = json!({
+            "name": pack,
+            "version": "1.0.0",
+            "author": "unknown",
+        });
+        serde_json::to_writer_pretty(File::create(manifest)?, &json)?;
+    }
+
+    Ok(directory
+        .join(CONFIG_DIR)
+        .join(format!("{}-{}.json", name, timestamp())))
+}
+
+/// The milliseconds since the epoch, to name the files saved by the game
+fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs() * 1000 + time.subsec_nanos() as u64 / 1_000_000)
+        .unwrap_or(0)
+}
+
+/// Returns the base pack in the directory, followed by the packs installed by the player.
+/// These are layered in the order of their directory names, eg: `10_trees` before `20_ice`.
+/// The installed packs without a valid manifest are skipped
+pub fn find_packs(base: &Path) -> Vec<Pack> {
+    let base_pack = Pack::load(base).unwrap_or_else(|error| {
+        panic!(
+            "Could not load the base pack in {}: {}",
+            base.display(),
+            error
+        )
+    });
+    let mut packs = vec![base_pack];
+
+    if let Some(user_dir) = user_packs_dir() {
+        if let Ok(entries) = fs::read_dir(user_dir) {
+            let mut directories: Vec<PathBuf> = entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.is_dir())
+                .collect();
+            directories.sort();
+            for directory in directories {
+                match Pack::load(&directory) {
+                    Ok(pack) => packs.push(pack),
+                    Err(error) => eprintln!(
+                        "Skipping the pack in {}, its {} could not be read: {}",
+                        directory.display(),
+                        MANIFEST_FILE,
+                        error
+                    ),
+                }
+            }
+        }
+    }
+
+    packs
+}
+
+/// Everything described by the asset files
+pub struct Resources {
+    pub player_type: PlayerType,
+    pub object_types: Vec<ObjectType>,
+    pub tile_types: Vec<TileType>,
+    pub generator: Box<Generator>,
+    pub segments: Vec<Segment>,
+}
+
+/// The error of a file missing a field, or with an invalid one
+pub fn invalid<S: Into<String>>(message: S) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn string<'a>(json: &'a Value, field: &str) -> io::Result<&'a str> {
+    json[field]
+        .as_str()
+        .ok_or_else(|| invalid(format!("{} must be a string", field)))
+}
+
+fn float(json: &Value, field: &str) -> io::Result<f64> {
+    json[field]
+        .as_f64()
+        .ok_or_else(|| invalid(format!("{} must be a number", field)))
+}
+
+fn number(json: &Value, field: &str) -> io::Result<f32> {
+    float(json, field).map(|number| number as f32)
+}
+
+fn integer(json: &Value, field: &str) -> io::Result<u32> {
+    json[field]
+        .as_u64()
+        .map(|integer| integer as u32)
+        .ok_or_else(|| invalid(format!("{} must be a positive integer", field)))
+}
+
+fn object<'a>(json: &'a Value, field: &str) -> io::Result<&'a Value> {
+    if json[field].is_object() {
+        Ok(&json[field])
+    } else {
+        Err(invalid(format!("{} must be an object", field)))
+    }
+}
+
+/// The path of a description file in its pack, as shown in the errors
+pub fn config_file(name: &str) -> String {
+    format!("{}/{}.json", CONFIG_DIR, name)
+}
+
+/// Returns the name and content of the json files of the asset packs.
+/// The packs are layered in order: a file of a later pack replaces the one with the same name.
+/// The files that could not be read are added to the errors
+pub fn read_config(packs: &[Pack], errors: &mut Vec<String>) -> Vec<(String, Value)> {
+    let mut files: Vec<(String, Value)> = Vec::new();
+
+    for pack in packs {
+        // Searchs for files in the config folder, a pack may only have textures.
+        // They are sorted so that the same seed produces the same slope on every system
+        let mut paths: Vec<PathBuf> = match fs::read_dir(pack.directory.join(CONFIG_DIR)) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().map_or(false, |ext| ext == "json"))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        paths.sort();
+        for path in paths {
+            // Types are referred to by the name of their file,
+            // an overridden type keeps its place so that the generation stays the same
+            let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+            let json = match read_json(&path) {
+                Ok(json) => json,
+                Err(error) => {
+                    errors.push(format!("{}: {}", config_file(&name), error));
+                    continue;
+                }
+            };
+            match files.iter().position(|&(ref other, _)| *other == name) {
+                Some(i) => files[i].1 = json,
+                None => files.push((name, json)),
+            }
+        }
+    }
+
+    files
+}
+
+fn read_json(path: &Path) -> io::Result<Value> {
+    let mut json_src = String::new();
+    File::open(path)?.read_to_string(&mut json_src)?;
+    Ok(serde_json::from_str(&json_src)?)
+}
+
+/// Loads the types from the json files of the asset packs, together with the atlas of all their textures.
+/// The packs are layered in order: a file of a later pack replaces the one with the same name,
+/// and its textures replace the ones with the same name
+pub fn load_resources(packs: &[Pack]) -> (Resources, Atlas) {
+    try_load_resources(packs).unwrap_or_else(|errors| {
+        panic!(
+            "Could not load the asset packs:\n{}",
+            errors.join("\n")
+        )
+    })
+}
+
+/// The directory of the base pack of the repository
+#[cfg(test)]
+pub fn base_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("resources")
+}
+
+/// Loads the base pack of the repository, for the tests that need real resources
+#[cfg(test)]
+pub fn base_pack() -> (Resources, Atlas) {
+    let pack = Pack::load(&base_dir()).expect("Could not load the base pack");
+    load_resources(&[pack])
+}
+
+/// Writes a pack in a new temporary directory, with the description files
+/// and the textures of one color. The name must be different for each test
+#[cfg(test)]
+pub fn temp_pack(name: &str, config: &[(&str, Value)], textures: &[&str]) -> PathBuf {
+    use std::env;
+    use std::process;
+
+    let directory = env::temp_dir().join(format!("skii-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(directory.join(CONFIG_DIR)).unwrap();
+    fs::create_dir_all(directory.join(TEXTURES_DIR)).unwrap();
+
+    let manifest = json!({ "name": name, "version": "1.0.0", "author": "test" });
+    serde_json::to_writer(File::create(directory.join(MANIFEST_FILE)).unwrap(), &manifest).unwrap();
+    for &(file, ref json) in config {
+        let path = directory.join(config_file(file));
+        serde_json::to_writer(File::create(path).unwrap(), json).unwrap();
+    }
+    for texture in textures {
+        let image = RgbaImage::from_pixel(16, 16, image::Rgba([200, 30, 30, 255]));
+        image.save(directory.join(TEXTURES_DIR).join(texture)).unwrap();
+    }
+
+    directory
+}
+
+/// The resources of `base_pack`, without its atlas
+#[cfg(test)]
+pub fn base_resources() -> Resources {
+    base_pack().0
+}
+
+/// Loads the asset packs like `load_resources`,
+/// or returns every problem that stops them from loading, prefixed by the file
+pub fn try_load_resources(packs: &[Pack]) -> Result<(Resources, Atlas), Vec<String>> {
+    let mut errors = Vec::new();
+    let files = read_config(packs, &mut errors);
+
+    // All the textures are packed together before creating the types,
+    // the files with missing textures are not loaded
+    let mut atlas_builder = AtlasBuilder::new();
+    let mut loaded = Vec::new();
+    for (name, json) in files {
+        match add_textures(&json["properties"], &mut atlas_builder, packs) {
+            Ok(()) => loaded.push((name, json)),
+            Err(error) => errors.push(format!("{}: {}", config_file(&name), error)),
+        }
+    }
+    let atlas = atlas_builder.build();
 
-pub fn load_resources(ctx: &mut Context) -> (PlayerType, Vec<ObjectType>, Vec<TileType>) {
     let mut player = None;
+    let mut generator = None;
     let mut object_types: Vec<ObjectType> = Vec::new();
     let mut tile_types: Vec<TileType> = Vec::new();
+    // The segments with the file they were loaded from
+    let mut segments: Vec<(String, Segment)> = Vec::new();
 
-    // Searchs for files in the assets folder
-    let paths = ctx.filesystem.read_dir(ASSETS_PATH).unwrap();
-    for path in paths {
-        if let Some(ext) = path.extension() {
-            // If the file is a json
-            if ext.to_str().unwrap() == "json" {
-                let mut json_src: String = "".to_string();
-                ctx.filesystem
-                    .open(path.clone())
-                    .unwrap()
-                    .read_to_string(&mut json_src)
-                    .expect("Could not open asset file");
-                let json: Value = serde_json::from_str(&json_src).unwrap();
-                if let Value::String(ref type_name) = json["type"] {
-                    match &type_name[..] {
-                        "tile" => {
-                            let tile = load_tile(&json["properties"], ctx);
-                            tile_types.push(tile);
-                        }
-                        "object" => {
-                            let obj = load_object(&json["properties"], ctx);
-                            object_types.push(obj);
-                        }
-                        "player" => {
-                            player = Some(load_player(&json["properties"], ctx));
-                        }
-                        _ => panic!("Unknown object type"),
-                    }
-                } else {
-                    panic!("Type not specified");
-                }
+    for (name, json) in loaded {
+        let file = config_file(&name);
+        let properties = &json["properties"];
+        if !properties.is_object() {
+            errors.push(format!("{}: the properties are missing", file));
+            continue;
+        }
+
+        let result = match json["type"].as_str() {
+            Some("tile") => load_tile(name, properties, &atlas).map(|tile| tile_types.push(tile)),
+            Some("object") => {
+                load_object(name, properties, &atlas).map(|obj| object_types.push(obj))
+            }
+            Some("player") => load_player(properties, &atlas).map(|loaded| player = Some(loaded)),
+            Some("generator") => {
+                load_generator(properties).map(|loaded| generator = Some(loaded))
+            }
+            Some("segment") => {
+                load_segment(properties).map(|segment| segments.push((file.clone(), segment)))
+            }
+            Some(type_name) => Err(invalid(format!("unknown type {}", type_name))),
+            None => Err(invalid("the type is missing")),
+        };
+        if let Err(error) = result {
+            errors.push(format!("{}: {}", file, error));
+        }
+    }
+
+    if player.is_none() {
+        errors.push(format!("{}: no player file was found", CONFIG_DIR));
+    }
+    if tile_types.is_empty() {
+        errors.push(format!("{}: no tile file was found", CONFIG_DIR));
+    }
+
+    // Check that segments only use existing types
+    for &(ref file, ref segment) in &segments {
+        for name in segment.tiles.iter().flat_map(|row| row.iter()).flat_map(|tile| tile) {
+            if !tile_types.iter().any(|tile_type| &tile_type.name == name) {
+                errors.push(format!("{}: unknown tile {}", file, name));
+            }
+        }
+        for &(ref name, _, _) in &segment.objects {
+            if !object_types.iter().any(|object_type| &object_type.name == name) {
+                errors.push(format!("{}: unknown object {}", file, name));
             }
         }
     }
 
-    let player = player.expect("A player asset could not be found");
-    (player, object_types, tile_types)
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let resources = Resources {
+        player_type: player.expect("The player was checked above"),
+        object_types,
+        tile_types,
+        // Without a generator file fall back on the cellular one
+        generator: generator.unwrap_or_else(|| Box::new(CellularGenerator)),
+        segments: segments.into_iter().map(|(_, segment)| segment).collect(),
+    };
+    Ok((resources, atlas))
 }
 
-fn load_tile(json: &Value, ctx: &mut Context) -> TileType {
-    let texture = load_texture(ctx, json["texture"].as_str().unwrap());
-    let forward_friction = json["forward_friction"].as_f64().unwrap() as f32;
-    let sideway_friction = json["sideway_friction"].as_f64().unwrap() as f32;
-    let distribution = json["distribution"].as_f64().unwrap() as f32;
+fn load_tile(name: String, json: &Value, atlas: &Atlas) -> io::Result<TileType> {
+    let texture = load_sprite(atlas, string(json, "texture")?)?;
+    let forward_friction = number(json, "forward_friction")?;
+    let sideway_friction = number(json, "sideway_friction")?;
+    let distribution = number(json, "distribution")?;
+    let noise_band = if json["noise_band"].is_null() {
+        None
+    } else {
+        let band = object(json, "noise_band")?;
+        let (min, max) = (number(band, "min")?, number(band, "max")?);
+        if min >= max {
+            return Err(invalid(
+                "the noise band min must be lower than max".to_owned(),
+            ));
+        }
+        Some((min, max))
+    };
+
+    // Tiles without a depth leave average marks
+    let trail_depth = if json["trail_depth"].is_null() {
+        0.5
+    } else {
+        number(json, "trail_depth")?
+    };
+    let particles = if json["particles"].is_null() {
+        None
+    } else {
+        Some(load_particles(object(json, "particles")?, atlas)?)
+    };
 
-    TileType {
+    Ok(TileType {
+        name,
         texture,
         forward_friction,
         sideway_friction,
         distribution,
-    }
+        noise_band,
+        trail_depth,
+        particles,
+        glyph: load_glyph(json)?,
+    })
 }
 
-fn load_object(json: &Value, ctx: &mut Context) -> ObjectType {
-    let texture = load_texture(ctx, json["texture"].as_str().unwrap());
-    let distribution = json["distribution"].as_f64().unwrap() as f32;
-    let hitbox_json = json["hitbox"].as_object().unwrap();
+fn load_object(name: String, json: &Value, atlas: &Atlas) -> io::Result<ObjectType> {
+    let texture = load_sprite(atlas, string(json, "texture")?)?;
+    let distribution = number(json, "distribution")?;
+    let hitbox_json = object(json, "hitbox")?;
     let hitbox = Vector2::new(
-        hitbox_json["width"].as_f64().unwrap() as f32,
-        hitbox_json["height"].as_f64().unwrap() as f32,
+        number(hitbox_json, "width")?,
+        number(hitbox_json, "height")?,
     );
 
-    ObjectType {
+    Ok(ObjectType {
+        name,
         distribution,
         hitbox,
         texture,
+        animations: load_animations(json, atlas)?,
+        glyph: load_glyph(json)?,
+    })
+}
+
+fn load_player(json: &Value, atlas: &Atlas) -> io::Result<PlayerType> {
+    Ok(PlayerType {
+        texture: load_sprite(atlas, string(json, "texture")?)?,
+        animations: load_animations(json, atlas)?,
+    })
+}
+
+/// Loads the animation of each state, types without animations get none
+fn load_animations(json: &Value, atlas: &Atlas) -> io::Result<Animations> {
+    let mut animations = Animations::new();
+    if json["animations"].is_null() {
+        return Ok(animations);
+    }
+
+    let states = json["animations"]
+        .as_object()
+        .ok_or_else(|| invalid("animations must be an object"))?;
+    for (state, animation_json) in states {
+        let animation = load_animation(animation_json, atlas).map_err(|error| {
+            io::Error::new(error.kind(), format!("{}: {}", state, error))
+        })?;
+        animations.insert(state.clone(), animation);
+    }
+
+    Ok(animations)
+}
+
+fn load_animation(json: &Value, atlas: &Atlas) -> io::Result<Animation> {
+    let sheet = load_sprite(atlas, string(json, "sheet")?)?;
+    let frame_width = integer(json, "frame_width")?;
+    let frame_height = integer(json, "frame_height")?;
+    let frame_time = number(json, "frame_time")?;
+    let loop_mode = match json["loop"].as_str().unwrap_or("loop") {
+        "loop" => LoopMode::Loop,
+        "once" => LoopMode::Once,
+        "ping_pong" => LoopMode::PingPong,
+        name => return Err(invalid(format!("unknown loop mode {}", name))),
+    };
+
+    if frame_width == 0 || frame_height == 0 {
+        return Err(invalid("the frames have no size"));
+    }
+
+    // The frames are numbered from left to right, and from top to bottom
+    let columns = sheet.width / frame_width;
+    let count = columns * (sheet.height / frame_height);
+    let frames_json = json["frames"]
+        .as_array()
+        .ok_or_else(|| invalid("frames must be a list"))?;
+    let mut frames = Vec::new();
+    for frame in frames_json {
+        let frame = frame
+            .as_u64()
+            .ok_or_else(|| invalid("the frames must be positive integers"))?;
+        if frame >= u64::from(count) {
+            return Err(invalid(format!(
+                "the frame {} is not in the sheet, which has {} frames",
+                frame, count
+            )));
+        }
+        let frame = frame as u32;
+        let x = frame % columns * frame_width;
+        let y = frame / columns * frame_height;
+        frames.push(sheet.sub_sprite(x, y, frame_width, frame_height));
+    }
+    if frames.is_empty() {
+        return Err(invalid("the animation has no frames"));
+    }
+
+    Ok(Animation {
+        frames,
+        frame_time,
+        loop_mode,
+    })
+}
+
+fn load_generator(json: &Value) -> io::Result<Box<Generator>> {
+    match string(json, "name")? {
+        "noise" => {
+            let scale = float(json, "scale")?;
+            let octaves = integer(json, "octaves")?;
+            if octaves == 0 {
+                return Err(invalid(
+                    "the noise generator needs at least one octave".to_owned(),
+                ));
+            }
+            let persistence = float(json, "persistence")?;
+            Ok(Box::new(NoiseGenerator::new(scale, octaves, persistence)))
+        }
+        name => generator_from_name(name).ok_or_else(|| invalid(format!("unknown generator {}", name))),
+    }
+}
+
+/// Loads the character shown in the terminal, white when the color is missing
+fn load_glyph(json: &Value) -> io::Result<Option<Glyph>> {
+    if json["glyph"].is_null() {
+        return Ok(None);
     }
+    let mut characters = string(json, "glyph")?.chars();
+    let character = match (characters.next(), characters.next()) {
+        (Some(character), None) => character,
+        _ => return Err(invalid("glyph must be a single character")),
+    };
+
+    let color = if json["glyph_color"].is_null() {
+        Color::new(1.0, 1.0, 1.0, 1.0)
+    } else {
+        let color_json = object(json, "glyph_color")?;
+        Color::new(
+            number(color_json, "r")?,
+            number(color_json, "g")?,
+            number(color_json, "b")?,
+            1.0,
+        )
+    };
+
+    Ok(Some(Glyph { character, color }))
 }
 
-fn load_player(json: &Value, ctx: &mut Context) -> PlayerType {
-    let texture = load_texture(ctx, json["texture"].as_str().unwrap());
+fn load_particles(json: &Value, atlas: &Atlas) -> io::Result<ParticleSettings> {
+    let texture = load_sprite(atlas, string(json, "texture")?)?;
+    let lifetime = number(json, "lifetime")?;
+    let count = integer(json, "count")?;
+    let color_json = object(json, "color")?;
+    let color = Color::new(
+        number(color_json, "r")?,
+        number(color_json, "g")?,
+        number(color_json, "b")?,
+        number(color_json, "a")?,
+    );
 
-    PlayerType { texture }
+    Ok(ParticleSettings {
+        texture,
+        lifetime,
+        color,
+        count,
+    })
 }
 
-fn load_texture(ctx: &mut Context, tex_name: &str) -> Image {
-    let path = "/textures/".to_owned() + tex_name;
-    let mut image = Image::new(ctx, path).expect("Error loading texture file");
-    image.set_filter(FilterMode::Nearest);
-    image
+fn load_segment(json: &Value) -> io::Result<Segment> {
+    Ok(Segment {
+        rate: number(json, "rate")?,
+        tiles: load_rows(json)?,
+        objects: load_objects(json)?,
+    })
+}
+
+/// Reads the tile rows of a segment or map, as tile names.
+/// Maps each character of the tile rows to a tile name with the legend,
+/// the characters missing from it are `None`
+pub fn load_rows(json: &Value) -> io::Result<Vec<Vec<Option<String>>>> {
+    let legend = json["legend"]
+        .as_object()
+        .ok_or_else(|| invalid("legend must be an object"))?;
+    for (character, name) in legend {
+        if character.chars().count() != 1 {
+            return Err(invalid(format!(
+                "the legend key {} must be a single character",
+                character
+            )));
+        }
+        if !name.is_string() {
+            return Err(invalid(format!(
+                "the tile of {} must be a string",
+                character
+            )));
+        }
+    }
+
+    // The rows are written as they appear on screen,
+    // so the last one is the first reached by the player
+    let rows_json = json["tiles"]
+        .as_array()
+        .ok_or_else(|| invalid("tiles must be a list of strings"))?;
+    let mut tiles = Vec::new();
+    for row_json in rows_json.iter().rev() {
+        let row = row_json
+            .as_str()
+            .ok_or_else(|| invalid("tiles must be a list of strings"))?
+            .chars()
+            .map(|tile| {
+                legend
+                    .get(&tile.to_string())
+                    .and_then(|name| name.as_str())
+                    .map(|name| name.to_owned())
+            })
+            .collect();
+        tiles.push(row);
+    }
+
+    Ok(tiles)
+}
+
+/// Reads the objects of a segment or map: their names, positions and rotations
+pub fn load_objects(json: &Value) -> io::Result<Vec<(String, Vector2, f32)>> {
+    let mut objects = Vec::new();
+    if !json["objects"].is_null() {
+        let objects_json = json["objects"]
+            .as_array()
+            .ok_or_else(|| invalid("objects must be a list"))?;
+        for object_json in objects_json {
+            let name = string(object_json, "object")?.to_owned();
+            let position = Vector2::new(number(object_json, "x")?, number(object_json, "y")?);
+            let rotation = if object_json["rotation"].is_null() {
+                0.0
+            } else {
+                number(object_json, "rotation")?
+            };
+            objects.push((name, position, rotation));
+        }
+    }
+
+    Ok(objects)
+}
+
+/// Adds the textures used in the properties to the atlas
+fn add_textures(json: &Value, atlas_builder: &mut AtlasBuilder, packs: &[Pack]) -> io::Result<()> {
+    let mut textures = vec![&json["texture"], &json["particles"]["texture"]];
+    if let Some(states) = json["animations"].as_object() {
+        textures.extend(states.values().map(|animation| &animation["sheet"]));
+    }
+
+    for texture in textures {
+        if let Some(tex_name) = texture.as_str() {
+            if !atlas_builder.contains(tex_name) {
+                let data = load_texture_data(packs, tex_name)?;
+                atlas_builder.add(tex_name, data);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns where the texture was packed in the atlas
+fn load_sprite(atlas: &Atlas, tex_name: &str) -> io::Result<Sprite> {
+    atlas
+        .sprite(tex_name)
+        .ok_or_else(|| invalid(format!("the texture {} is missing from the atlas", tex_name)))
+}
+
+/// Reads the pixels of a texture, to be packed in the atlas.
+/// The texture is taken from the last pack having it
+fn load_texture_data(packs: &[Pack], tex_name: &str) -> io::Result<RgbaImage> {
+    let path = packs
+        .iter()
+        .rev()
+        .map(|pack| pack.directory.join(TEXTURES_DIR).join(tex_name))
+        .find(|path| path.is_file())
+        .ok_or_else(|| invalid(format!("the texture {} could not be found", tex_name)))?;
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+
+    image::load_from_memory(&bytes)
+        .map(|texture| texture.to_rgba())
+        .map_err(|error| {
+            invalid(format!(
+                "the texture {} could not be decoded: {}",
+                tex_name, error
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segment_round_trip() {
+        let tile = |name: &str| Some(name.to_owned());
+        let segment = Segment {
+            rate: 0.05,
+            tiles: vec![
+                vec![tile("snow"), None, tile("icy_snow")],
+                vec![None, tile("deep_snow"), tile("snow")],
+            ],
+            objects: vec![
+                ("tree1".to_owned(), Vector2::new(1.5, 0.5), 0.0),
+                ("tree2".to_owned(), Vector2::new(0.5, 1.25), 0.75),
+            ],
+        };
+
+        let json = segment.to_json().unwrap();
+        assert_eq!(json["type"], "segment");
+        let loaded = load_segment(&json["properties"]).unwrap();
+        assert_eq!(loaded.rate, segment.rate);
+        assert_eq!(loaded.tiles, segment.tiles);
+        assert_eq!(loaded.objects, segment.objects);
+    }
+
+    fn tile(texture: &str, distribution: f64) -> Value {
+        json!({
+            "type": "tile",
+            "properties": {
+                "texture": texture,
+                "forward_friction": 0.1,
+                "sideway_friction": 5.0,
+                "distribution": distribution,
+            },
+        })
+    }
+
+    #[test]
+    fn later_packs_override_files_by_name() {
+        let directory = temp_pack("override", &[("snow", tile("snow.png", 0.25))], &[]);
+        let packs = [Pack::load(&base_dir()).unwrap(), Pack::load(&directory).unwrap()];
+        let base_files = read_config(&packs[..1], &mut Vec::new());
+        let mut errors = Vec::new();
+        let files = read_config(&packs, &mut errors);
+        fs::remove_dir_all(directory).unwrap();
+
+        assert!(errors.is_empty(), "{:?}", errors);
+        // The file keeps its place among the others, with the content of the later pack
+        let names = |files: &[(String, Value)]| -> Vec<String> {
+            files.iter().map(|&(ref name, _)| name.clone()).collect()
+        };
+        assert_eq!(names(&files), names(&base_files));
+        let snow = files.iter().find(|&&(ref name, _)| name == "snow").unwrap();
+        assert_eq!(snow.1["properties"]["distribution"], 0.25);
+    }
+
+    #[test]
+    fn later_packs_shadow_textures() {
+        let directory = temp_pack("shadow", &[], &["snow.png"]);
+        let packs = [Pack::load(&base_dir()).unwrap(), Pack::load(&directory).unwrap()];
+        let base_texture = load_texture_data(&packs[..1], "snow.png").unwrap();
+        let shadowed = load_texture_data(&packs, "snow.png").unwrap();
+        let inherited = load_texture_data(&packs, "tree1.png").unwrap();
+        fs::remove_dir_all(directory).unwrap();
+
+        // The pack texture is all of one color, unlike the base one
+        assert_eq!(shadowed.dimensions(), (16, 16));
+        assert!(shadowed.pixels().all(|pixel| pixel.data == [200, 30, 30, 255]));
+        assert!(base_texture.pixels().any(|pixel| pixel.data != [200, 30, 30, 255]));
+        assert!(inherited.pixels().any(|pixel| pixel.data != [200, 30, 30, 255]));
+        assert!(load_texture_data(&packs, "lava.png").is_err());
+    }
 }
@@ -1,26 +1,566 @@
+extern crate clap;
+#[cfg(feature = "window")]
 extern crate ggez;
+extern crate serde_json;
 extern crate skii;
 
-use std::{env, path};
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
+use clap::{App, Arg, ArgMatches, SubCommand};
+#[cfg(feature = "window")]
 use ggez::ContextBuilder;
+#[cfg(feature = "window")]
 use ggez::conf;
+#[cfg(feature = "window")]
 use ggez::event;
+use skii::game::{PlayerInput, World, DEFAULT_SLOPE_WIDTH, MIN_SLOPE_WIDTH};
+use skii::loader::{find_packs, load_resources, Pack};
+use skii::map::Map;
+use skii::random::random_seed;
+use skii::replay::{pack_versions, Replay};
+use skii::simulation::{
+    controller_from_name, new_world, simulate_run, to_csv, to_json, CONTROLLERS, MODES,
+};
+use skii::snapshot;
+#[cfg(feature = "window")]
+use skii::states::Playing;
+use skii::terminal;
+use skii::terminal::render;
+use skii::validate::validate_pack;
+
+// The updates per second of the game, also used by the headless mode
+const UPDATES_PER_SECOND: f32 = 60.0;
+// Without a replay the headless mode stops after ten minutes
+const HEADLESS_UPDATES: usize = 36_000;
+// The rows of the world in the headless mode, when not replaying a run
+const HEADLESS_HEIGHT: u32 = 16;
 
 fn main() {
-    let mut cb = ContextBuilder::new("Skii", "Piripant")
-        .window_setup(conf::WindowSetup::default().title("Skii"))
-        .window_mode(conf::WindowMode::default().dimensions(720, 720));
+    let matches = App::new("Skii")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("A 2D top-down skiing game with a procedurally generated landscape")
+        .arg(
+            Arg::with_name("window")
+                .long("window")
+                .value_name("WIDTHxHEIGHT")
+                .default_value("720x720")
+                .validator(|value| parse_size(&value).map(|_| ()))
+                .help("The size of the window"),
+        )
+        .arg(
+            Arg::with_name("fullscreen")
+                .long("fullscreen")
+                .help("Covers the whole screen"),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .value_name("SEED")
+                .validator(is_number::<u64>)
+                .help("Generates the same slope on every run"),
+        )
+        .arg(
+            Arg::with_name("mode")
+                .long("mode")
+                .value_name("MODE")
+                .possible_values(&MODES)
+                .default_value("classic")
+                .help("The generator of the slope, classic uses the one of the asset pack"),
+        )
+        .arg(
+            Arg::with_name("slope-width")
+                .long("slope-width")
+                .value_name("TILES")
+                .validator(is_slope_width)
+                .help("The number of tiles across the slope"),
+        )
+        .arg(
+            Arg::with_name("start")
+                .long("start")
+                .value_name("METERS")
+                .validator(is_number::<u32>)
+                .help("The distance every run starts from"),
+        )
+        .arg(
+            Arg::with_name("assets")
+                .long("assets")
+                .value_name("DIR")
+                .help("The base asset pack, holding the config, textures and fonts folders"),
+        )
+        .arg(
+            Arg::with_name("pack")
+                .long("pack")
+                .value_name("DIR")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Layers an asset pack over the installed ones, can be repeated"),
+        )
+        .arg(
+            Arg::with_name("replay")
+                .long("replay")
+                .value_name("FILE")
+                .help("Plays back a recorded run"),
+        )
+        .arg(
+            Arg::with_name("map")
+                .long("map")
+                .value_name("FILE")
+                .conflicts_with_all(&["replay", "record"])
+                .help("Starts every run on an exported map"),
+        )
+        .arg(
+            Arg::with_name("export-map")
+                .long("export-map")
+                .value_name("FILE")
+                .requires("headless")
+                .help("With --headless saves the rows and objects at the end of the run"),
+        )
+        .arg(
+            Arg::with_name("record")
+                .long("record")
+                .value_name("FILE")
+                .help("Records each run, the file holds the last one that crashed"),
+        )
+        .arg(
+            Arg::with_name("headless")
+                .long("headless")
+                .help("Runs the game without a window and prints the result of the run"),
+        )
+        .arg(
+            Arg::with_name("terminal")
+                .long("terminal")
+                .help("Plays in the terminal, with --headless prints the final slope"),
+        )
+        .arg(
+            Arg::with_name("capture-scale")
+                .long("capture-scale")
+                .value_name("SCALE")
+                .validator(is_number::<f32>)
+                .help("The size of the screenshots and recordings, compared to the window"),
+        )
+        .arg(
+            Arg::with_name("frame-skip")
+                .long("frame-skip")
+                .value_name("UPDATES")
+                .validator(is_number::<u32>)
+                .help("The updates skipped after each recorded frame"),
+        )
+        .arg(
+            Arg::with_name("snapshot")
+                .long("snapshot")
+                .value_name("FILE")
+                .requires("headless")
+                .help("With --headless saves a png of the slope at the end of the run"),
+        )
+        .arg(
+            Arg::with_name("dump-atlas")
+                .long("dump-atlas")
+                .value_name("DIR")
+                .help("Saves the packed textures in the directory"),
+        )
+        .subcommand(
+            SubCommand::with_name("validate")
+                .about("Checks an asset pack without launching the game")
+                .arg(
+                    Arg::with_name("PACK")
+                        .required(true)
+                        .help("The asset pack directory, layered over the base pack"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("simulate")
+                .about("Plays many runs without a window and prints their statistics")
+                .after_help(
+                    "The slope is chosen with the options given before simulate, \
+                     eg: skii --mode noise --seed 1 simulate --runs 50",
+                )
+                .arg(
+                    Arg::with_name("runs")
+                        .long("runs")
+                        .value_name("COUNT")
+                        .default_value("100")
+                        .validator(is_number::<usize>)
+                        .help("The number of runs, each one uses the next seed"),
+                )
+                .arg(
+                    Arg::with_name("ticks")
+                        .long("ticks")
+                        .value_name("COUNT")
+                        .default_value("3600")
+                        .validator(is_number::<usize>)
+                        .help("The most updates of each run, 60 are a second"),
+                )
+                .arg(
+                    Arg::with_name("controller")
+                        .long("controller")
+                        .value_name("NAME")
+                        .possible_values(&CONTROLLERS)
+                        .default_value("zigzag")
+                        .help("What steers the player"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .possible_values(&["csv", "json"])
+                        .default_value("csv")
+                        .help("csv prints each run, json also the statistics of all of them"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Writes the statistics in the file instead of printing them"),
+                ),
+        )
+        .get_matches();
 
-    if let Ok(manifest_dir) = env::var("CARGO_MANIFEST_DIR") {
-        let mut path = path::PathBuf::from(manifest_dir);
-        path.push("resources/");
-        cb = cb.add_resource_path(path);
+    let assets = assets_dir(&matches);
+    if let Some(validate) = matches.subcommand_matches("validate") {
+        run_validate(Path::new(validate.value_of("PACK").unwrap()), &assets);
+        return;
+    }
+    let mut packs = find_packs(&assets);
+    if let Some(directories) = matches.values_of("pack") {
+        for directory in directories {
+            packs.push(Pack::load(Path::new(directory)).unwrap_or_else(|error| {
+                eprintln!("Could not load the pack in {}: {}", directory, error);
+                std::process::exit(1);
+            }));
+        }
+    }
+    let replay = matches.value_of("replay").map(|path| {
+        Replay::load(Path::new(path)).unwrap_or_else(|error| {
+            eprintln!("Could not load the replay: {}", error);
+            std::process::exit(1);
+        })
+    });
+    if let Some(ref replay) = replay {
+        for mismatch in replay.pack_mismatches(&packs) {
+            eprintln!("The replay may differ from the run, {}", mismatch);
+        }
+    }
+
+    let map = matches.value_of("map").map(|path| {
+        Map::load(Path::new(path)).unwrap_or_else(|error| {
+            eprintln!("Could not load the map: {}", error);
+            std::process::exit(1);
+        })
+    });
+
+    if let Some(simulate) = matches.subcommand_matches("simulate") {
+        run_simulate(&matches, simulate, &packs, map.as_ref());
+        return;
+    }
+
+    if matches.is_present("terminal") && !matches.is_present("headless") {
+        run_terminal(&matches, &packs, map.as_ref());
+        return;
+    }
+
+    if matches.is_present("headless") {
+        run_headless(&matches, &packs, replay, map);
     } else {
-        println!("Not building from cargo?  Ok.");
+        run_window(&matches, &assets, &packs, replay, map);
     }
+}
+
+#[cfg(feature = "window")]
+fn run_window(
+    matches: &ArgMatches,
+    assets: &Path,
+    packs: &[Pack],
+    replay: Option<Replay>,
+    map: Option<Map>,
+) {
+    let (width, height) = parse_size(matches.value_of("window").unwrap()).unwrap();
+    let mut window_mode = conf::WindowMode::default().dimensions(width, height);
+    if matches.is_present("fullscreen") {
+        window_mode = window_mode.fullscreen_type(conf::FullscreenType::Desktop);
+    }
+
+    let cb = ContextBuilder::new("Skii", "Piripant")
+        .window_setup(conf::WindowSetup::default().title("Skii").resizable(true))
+        .window_mode(window_mode)
+        .add_resource_path(assets);
 
     let ctx = &mut cb.build().unwrap();
-    let state = &mut skii::renderer::ViewState::new(ctx).unwrap();
+    let state = &mut skii::renderer::ViewState::new(ctx, packs).unwrap();
+
+    state.seed = parse(matches, "seed");
+    state.start = parse(matches, "start").unwrap_or(0);
+    state.record_path = matches.value_of("record").map(PathBuf::from);
+    if let Some(scale) = parse(matches, "capture-scale") {
+        state.capture.settings.scale = scale;
+    }
+    if let Some(frame_skip) = parse(matches, "frame-skip") {
+        state.capture.settings.frame_skip = frame_skip;
+    }
+    if let Some(width) = parse(matches, "slope-width") {
+        state.slope_width = width;
+    }
+    state.select_mode(matches.value_of("mode").unwrap());
+
+    // A replay is played back right away, without the main menu
+    if let Some(replay) = replay {
+        state.play_replay(replay);
+        state.set_state(Box::new(Playing));
+    }
+    // And so is a map
+    if let Some(map) = map {
+        state.play_map(map).unwrap_or_else(|error| {
+            eprintln!("Could not play the map: {}", error);
+            std::process::exit(1);
+        });
+        state.set_state(Box::new(Playing));
+    }
+
+    if let Some(directory) = matches.value_of("dump-atlas") {
+        state
+            .atlas
+            .save(Path::new(directory))
+            .expect("Could not save the atlas");
+    }
+
     event::run(ctx, state).unwrap();
 }
+
+#[cfg(not(feature = "window"))]
+fn run_window(
+    _matches: &ArgMatches,
+    _assets: &Path,
+    _packs: &[Pack],
+    _replay: Option<Replay>,
+    _map: Option<Map>,
+) {
+    eprintln!("This build has no window, play with --terminal or run with --headless");
+    std::process::exit(1);
+}
+
+/// Plays a run without opening a window, following the replay if given.
+/// Without one the player goes straight down the slope, or down the map if given
+fn run_headless(
+    matches: &ArgMatches,
+    packs: &[Pack],
+    replay: Option<Replay>,
+    map: Option<Map>,
+) {
+    let mut run = match replay {
+        Some(ref replay) => Replay::new(
+            replay.seed,
+            &replay.mode,
+            replay.slope_width,
+            replay.height,
+            replay.start,
+        ),
+        None => Replay::new(
+            parse(matches, "seed").unwrap_or_else(random_seed),
+            matches.value_of("mode").unwrap(),
+            parse(matches, "slope-width").unwrap_or(DEFAULT_SLOPE_WIDTH),
+            HEADLESS_HEIGHT,
+            parse(matches, "start").unwrap_or(0),
+        ),
+    };
+    run.packs = pack_versions(packs);
+
+    let (resources, atlas) = load_resources(packs);
+    let mut world = new_world(resources, &run.mode, run.slope_width, run.height);
+    world.reset_seeded(run.slope_width, run.height, run.seed);
+    check_map(&mut world, map.as_ref());
+    world.advance(run.start);
+
+    let updates = replay
+        .as_ref()
+        .map_or(HEADLESS_UPDATES, |replay| replay.inputs.len());
+    let mut crashed = false;
+    for update in 0..updates {
+        let input = replay
+            .as_ref()
+            .map_or_else(PlayerInput::none, |replay| replay.input(update));
+        run.inputs.push(input);
+
+        let (_, collided) = world.step(&input, 1.0 / UPDATES_PER_SECOND);
+        if collided {
+            crashed = true;
+            break;
+        }
+    }
+
+    println!("seed: {}", run.seed);
+    println!(
+        "distance: {:.2} meters",
+        world.real_y + world.player.position.y
+    );
+    println!(
+        "time: {:.2} seconds",
+        run.inputs.len() as f32 / UPDATES_PER_SECOND
+    );
+    println!("crashed: {}", crashed);
+
+    if matches.is_present("terminal") {
+        for line in render(&world, crashed, terminal::is_terminal()) {
+            println!("{}", line);
+        }
+    }
+
+    if let Some(path) = matches.value_of("export-map") {
+        if let Err(error) = Map::from_world(&world).save(Path::new(path)) {
+            eprintln!("Could not save the map: {}", error);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(path) = matches.value_of("snapshot") {
+        // The picture is as big as the window would be
+        let size = parse_size(matches.value_of("window").unwrap()).unwrap();
+        snapshot::save(&world, &atlas, size, crashed, Path::new(path))
+            .expect("Could not save the snapshot");
+    }
+
+    if let Some(path) = matches.value_of("record") {
+        run.save(Path::new(path)).expect("Could not save the run");
+    }
+}
+
+/// Plays in the terminal, on a slope as tall as the one of the headless runs
+fn run_terminal(matches: &ArgMatches, packs: &[Pack], map: Option<&Map>) {
+    let (resources, _) = load_resources(packs);
+    let mut world = new_world(
+        resources,
+        matches.value_of("mode").unwrap(),
+        parse(matches, "slope-width").unwrap_or(DEFAULT_SLOPE_WIDTH),
+        HEADLESS_HEIGHT,
+    );
+    check_map(&mut world, map);
+
+    terminal::play(
+        &mut world,
+        map,
+        parse(matches, "seed"),
+        parse(matches, "start").unwrap_or(0),
+        1.0 / UPDATES_PER_SECOND,
+    ).expect("Could not play in the terminal");
+}
+
+/// Plays the runs with the controller, and prints or saves their statistics
+fn run_simulate(matches: &ArgMatches, simulate: &ArgMatches, packs: &[Pack], map: Option<&Map>) {
+    let (resources, _) = load_resources(packs);
+    let mut world = new_world(
+        resources,
+        matches.value_of("mode").unwrap(),
+        parse(matches, "slope-width").unwrap_or(DEFAULT_SLOPE_WIDTH),
+        HEADLESS_HEIGHT,
+    );
+    check_map(&mut world, map);
+    let mut controller = controller_from_name(simulate.value_of("controller").unwrap()).unwrap();
+
+    let first_seed = parse(matches, "seed").unwrap_or_else(random_seed);
+    let start = parse(matches, "start").unwrap_or(0);
+    let ticks = parse(simulate, "ticks").unwrap();
+    let runs: Vec<_> = (0..parse(simulate, "runs").unwrap())
+        .map(|i| {
+            let seed = first_seed.wrapping_add(i as u64);
+            simulate_run(
+                &mut world,
+                &mut *controller,
+                map,
+                seed,
+                start,
+                ticks,
+                1.0 / UPDATES_PER_SECOND,
+            )
+        })
+        .collect();
+
+    let output = match simulate.value_of("format").unwrap() {
+        "json" => serde_json::to_string_pretty(&to_json(&runs)).unwrap(),
+        _ => to_csv(&runs),
+    };
+    match simulate.value_of("output") {
+        Some(path) => {
+            let mut file = File::create(path).expect("Could not create the output file");
+            file.write_all(output.as_bytes())
+                .expect("Could not write the output file");
+        }
+        None => println!("{}", output),
+    }
+}
+
+/// Starts the world on the map, and exits with an error if the map does not fit the packs
+fn check_map(world: &mut World, map: Option<&Map>) {
+    if let Some(map) = map {
+        world.load_map(map).unwrap_or_else(|error| {
+            eprintln!("Could not play the map: {}", error);
+            std::process::exit(1);
+        });
+    }
+}
+
+/// Prints the problems found in the pack, and exits with an error if it cannot be loaded
+fn run_validate(directory: &Path, assets: &Path) {
+    // The pack is checked over the installed ones, as the game would load it, but not over itself
+    let directory_path = directory.canonicalize().ok();
+    let base: Vec<PathBuf> = find_packs(assets)
+        .into_iter()
+        .map(|pack| pack.directory)
+        .filter(|layer| layer.canonicalize().ok() != directory_path)
+        .collect();
+
+    let report = validate_pack(directory, &base);
+    println!("{}", report);
+    if !report.is_valid() {
+        std::process::exit(1);
+    }
+}
+
+/// The base asset pack directory: the one given with `--assets`,
+/// the resources of the source tree when run with cargo,
+/// or the resources folder next to the executable
+fn assets_dir(matches: &ArgMatches) -> PathBuf {
+    if let Some(directory) = matches.value_of("assets") {
+        return PathBuf::from(directory);
+    }
+    if let Ok(manifest_dir) = env::var("CARGO_MANIFEST_DIR") {
+        return PathBuf::from(manifest_dir).join("resources");
+    }
+
+    match env::current_exe() {
+        Ok(ref exe) if exe.with_file_name("resources").is_dir() => {
+            exe.with_file_name("resources")
+        }
+        _ => PathBuf::from("resources"),
+    }
+}
+
+/// Parses a `WIDTHxHEIGHT` size
+fn parse_size(value: &str) -> Result<(u32, u32), String> {
+    let mut parts = value.split('x').map(|part| part.parse::<u32>());
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(width)), Some(Ok(height)), None) => Ok((width, height)),
+        _ => Err("The size must be written as WIDTHxHEIGHT, eg: 1280x720".to_owned()),
+    }
+}
+
+fn is_number<T: FromStr>(value: String) -> Result<(), String> {
+    value
+        .parse::<T>()
+        .map(|_| ())
+        .map_err(|_| "It must be a positive number".to_owned())
+}
+
+fn is_slope_width(value: String) -> Result<(), String> {
+    match value.parse::<u32>() {
+        Ok(width) if width >= MIN_SLOPE_WIDTH => Ok(()),
+        _ => Err(format!("It must be at least {} tiles", MIN_SLOPE_WIDTH)),
+    }
+}
+
+/// Returns the value of the argument, already checked by its validator
+fn parse<T: FromStr>(matches: &ArgMatches, name: &str) -> Option<T> {
+    matches
+        .value_of(name)
+        .map(|value| value.parse().ok().expect("Validated argument"))
+}
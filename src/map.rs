@@ -0,0 +1,335 @@
+use serde_json;
+use serde_json::Value;
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use game::{Object, Segment, World, MIN_SLOPE_WIDTH};
+use types::Vector2;
+use loader::{invalid, load_objects, load_rows};
+
+// The characters given to the tiles in the legend, when the first one of their name is taken
+const LEGEND_CHARACTERS: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+
+/// A slope section stored by the names of its tiles and objects,
+/// eg: exported from a run to be attached to a bug report and played again
+pub struct Map {
+    // The tile names of each row, the first row is the first one reached by the player
+    pub tiles: Vec<Vec<String>>,
+    // The object names, positions relative to the bottom left of the map, and rotations
+    pub objects: Vec<(String, Vector2, f32)>,
+}
+
+impl Map {
+    /// Returns the rows and objects currently in the world
+    pub fn from_world(world: &World) -> Map {
+        let tiles = world
+            .tiles
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|&id| world.tile_types[id].name.clone())
+                    .collect()
+            })
+            .collect();
+        let objects = world.named_objects(&world.objects);
+
+        Map { tiles, objects }
+    }
+
+    pub fn width(&self) -> usize {
+        self.tiles.first().map_or(0, |row| row.len())
+    }
+
+    pub fn height(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn load(path: &Path) -> io::Result<Map> {
+        let mut json_src = String::new();
+        File::open(path)?.read_to_string(&mut json_src)?;
+        let json: Value = serde_json::from_str(&json_src)?;
+
+        Map::from_json(&json)
+    }
+
+    /// Reads a map in the format of `to_json`
+    pub fn from_json(json: &Value) -> io::Result<Map> {
+        if json["type"].as_str() != Some("map") {
+            return Err(invalid("The map has no valid type"));
+        }
+        let properties = &json["properties"];
+        let mut tiles = Vec::new();
+        for row in load_rows(properties)? {
+            let row: Option<Vec<String>> = row.into_iter().collect();
+            tiles.push(row.ok_or_else(|| invalid("The map has tiles missing from its legend"))?);
+        }
+        let width = tiles.first().map_or(0, |row: &Vec<String>| row.len());
+        if width == 0 || tiles.iter().any(|row| row.len() != width) {
+            return Err(invalid("The map rows must be as wide"));
+        }
+        let objects = load_objects(properties)?;
+
+        Ok(Map { tiles, objects })
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, &self.to_json()?)?;
+
+        Ok(())
+    }
+
+    /// Returns the map in a format close to the segments one:
+    /// a legend from characters to tile names, and the rows as they appear on screen
+    pub fn to_json(&self) -> io::Result<Value> {
+        let rows: Vec<Vec<Option<&str>>> = self.tiles
+            .iter()
+            .map(|row| row.iter().map(|name| Some(name.as_str())).collect())
+            .collect();
+        let (legend, tiles) = rows_json(&rows)?;
+
+        Ok(json!({
+            "type": "map",
+            "properties": {
+                "legend": legend,
+                "tiles": tiles,
+                "objects": objects_json(&self.objects),
+            },
+        }))
+    }
+}
+
+impl Segment {
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, &self.to_json()?)?;
+
+        Ok(())
+    }
+
+    /// Returns the segment in the format read by the loader,
+    /// the tiles kept from the generator are written as `.`
+    pub fn to_json(&self) -> io::Result<Value> {
+        let rows: Vec<Vec<Option<&str>>> = self.tiles
+            .iter()
+            .map(|row| row.iter().map(|tile| tile.as_ref().map(|name| name.as_str())).collect())
+            .collect();
+        let (legend, tiles) = rows_json(&rows)?;
+
+        Ok(json!({
+            "type": "segment",
+            "properties": {
+                "rate": self.rate,
+                "legend": legend,
+                "tiles": tiles,
+                "objects": objects_json(&self.objects),
+            },
+        }))
+    }
+}
+
+/// Gives each tile name a character: the first one of the name, or the first one still free.
+/// Fails when there are more tile names than characters
+fn legend<'a, I>(names: I) -> io::Result<Vec<(char, &'a str)>>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let mut legend: Vec<(char, &str)> = Vec::new();
+    for name in names {
+        if legend.iter().any(|&(_, other)| other == name) {
+            continue;
+        }
+        let candidates = name.chars().take(1).chain(LEGEND_CHARACTERS.chars());
+        let free = candidates
+            .filter(|character| character.is_alphanumeric())
+            .find(|character| legend.iter().all(|&(other, _)| other != *character))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "There are more tile types than the {} legend characters",
+                        LEGEND_CHARACTERS.len()
+                    ),
+                )
+            })?;
+        legend.push((free, name));
+    }
+
+    Ok(legend)
+}
+
+/// Returns the character given to the tile name by the legend
+fn character(legend: &[(char, &str)], name: &str) -> char {
+    legend
+        .iter()
+        .find(|&&(_, other)| other == name)
+        .map(|&(character, _)| character)
+        .expect("The tile is missing from the legend")
+}
+
+fn legend_json(legend: &[(char, &str)]) -> Value {
+    let mut json = serde_json::Map::new();
+    for &(character, name) in legend {
+        json.insert(character.to_string(), Value::from(name));
+    }
+
+    Value::Object(json)
+}
+
+/// Returns the legend and the rows as read by `load_rows`, the missing tiles are written as `.`
+fn rows_json(rows: &[Vec<Option<&str>>]) -> io::Result<(Value, Vec<String>)> {
+    let names = rows.iter().flat_map(|row| row.iter().filter_map(|&tile| tile));
+    let legend = legend(names)?;
+    let tiles = rows.iter()
+        .rev()
+        .map(|row| {
+            row.iter()
+                .map(|&tile| match tile {
+                    Some(name) => character(&legend, name),
+                    None => '.',
+                })
+                .collect()
+        })
+        .collect();
+
+    Ok((legend_json(&legend), tiles))
+}
+
+/// Returns the objects as read by `load_objects`
+fn objects_json(objects: &[(String, Vector2, f32)]) -> Value {
+    objects
+        .iter()
+        .map(|&(ref name, position, rotation)| {
+            json!({
+                "object": name,
+                "x": position.x,
+                "y": position.y,
+                "rotation": rotation,
+            })
+        })
+        .collect()
+}
+
+impl World {
+    /// Returns the objects by the name of their type, as they are stored in the maps and segments
+    pub fn named_objects(&self, objects: &[(usize, Object)]) -> Vec<(String, Vector2, f32)> {
+        objects
+            .iter()
+            .map(|&(id, ref object)| {
+                (
+                    self.object_types[id].name.clone(),
+                    object.position,
+                    object.rotation,
+                )
+            })
+            .collect()
+    }
+
+    /// Starts the run on the map, on a slope as wide as it: its first rows replace the world ones,
+    /// the others are placed as the player goes down, then the generator continues.
+    /// The world keeps its seed and height, and is left as it is when the map is invalid
+    pub fn load_map(&mut self, map: &Map) -> io::Result<()> {
+        let unknown =
+            |kind: &str, name: &str| invalid(format!("The map uses the unknown {} {}", kind, name));
+
+        if map.width() < MIN_SLOPE_WIDTH as usize {
+            return Err(invalid(format!(
+                "The map must be at least {} tiles wide",
+                MIN_SLOPE_WIDTH
+            )));
+        }
+
+        let mut rows = Vec::new();
+        for row in &map.tiles {
+            let mut ids = Vec::new();
+            for name in row {
+                ids.push(self.tile_id(name).ok_or_else(|| unknown("tile", name))?);
+            }
+            rows.push((ids, Vec::new()));
+        }
+        for &(ref name, position, rotation) in &map.objects {
+            let id = self.object_id(name).ok_or_else(|| unknown("object", name))?;
+            // Each object is kept with its row, relative to it
+            let row = position.y.max(0.0) as usize;
+            let objects = match rows.get_mut(row) {
+                Some(&mut (_, ref mut objects)) => objects,
+                None => return Err(invalid(format!("The object {} is past the map rows", name))),
+            };
+            let mut object = Object::new(Vector2::new(position.x, position.y - row as f32));
+            object.rotation = rotation;
+            objects.push((id, object));
+        }
+
+        let (height, seed) = (self.height(), self.seed);
+        self.reset_seeded(map.width() as u32, height as u32, seed);
+        self.tiles.clear();
+        self.objects.clear();
+        self.map_rows = rows.into_iter().collect();
+        while self.height() < height {
+            self.add_row();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use loader::base_resources;
+    use simulation::new_world;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn map_round_trip() {
+        let map = Map {
+            tiles: vec![
+                names(&["snow", "snow", "ice"]),
+                names(&["ice", "deep_snow", "snow"]),
+                names(&["snow", "snow", "snow"]),
+            ],
+            objects: vec![
+                ("tree1".to_owned(), Vector2::new(1.5, 0.25), 0.5),
+                ("tree2".to_owned(), Vector2::new(0.75, 2.5), 0.0),
+            ],
+        };
+
+        let loaded = Map::from_json(&map.to_json().unwrap()).unwrap();
+        assert_eq!(loaded.tiles, map.tiles);
+        assert_eq!(loaded.objects, map.objects);
+    }
+
+    #[test]
+    fn legend_runs_out_of_characters() {
+        let tiles: Vec<String> = (0..LEGEND_CHARACTERS.len() + 1)
+            .map(|i| format!("tile{}", i))
+            .collect();
+        let mut map = Map {
+            tiles: vec![tiles],
+            objects: Vec::new(),
+        };
+        assert!(map.to_json().is_err());
+
+        map.tiles[0].pop();
+        let loaded = Map::from_json(&map.to_json().unwrap()).unwrap();
+        assert_eq!(loaded.tiles, map.tiles);
+    }
+
+    #[test]
+    fn objects_past_the_rows_are_invalid() {
+        let mut world = new_world(base_resources(), "classic", 7, 16);
+        let mut map = Map {
+            tiles: vec![names(&["snow"; 7]); 4],
+            objects: vec![("tree1".to_owned(), Vector2::new(2.5, 3.5), 0.0)],
+        };
+        world.load_map(&map).unwrap();
+
+        map.objects[0].1.y = 4.5;
+        assert!(world.load_map(&map).is_err());
+    }
+}
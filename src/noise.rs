@@ -0,0 +1,99 @@
+use rand::Rng;
+
+/// A seeded 2D Perlin noise source
+pub struct Perlin {
+    // The permutation table, repeated twice to avoid wrapping the indices
+    permutation: Vec<usize>,
+}
+
+impl Perlin {
+    pub fn new<R: Rng>(rng: &mut R) -> Perlin {
+        let mut table: Vec<usize> = (0..256).collect();
+        rng.shuffle(&mut table);
+
+        let mut permutation = table.clone();
+        permutation.extend(table);
+
+        Perlin { permutation }
+    }
+
+    /// Returns the noise value at the given point, roughly in the -1.0..1.0 range
+    pub fn get(&self, x: f64, y: f64) -> f64 {
+        let cell_x = x.floor();
+        let cell_y = y.floor();
+        // The coordinates inside the cell
+        let x = x - cell_x;
+        let y = y - cell_y;
+        let cell_x = (cell_x as i64 & 255) as usize;
+        let cell_y = (cell_y as i64 & 255) as usize;
+
+        let p = &self.permutation;
+        let bottom_left = p[p[cell_x] + cell_y];
+        let bottom_right = p[p[cell_x + 1] + cell_y];
+        let top_left = p[p[cell_x] + cell_y + 1];
+        let top_right = p[p[cell_x + 1] + cell_y + 1];
+
+        let fade_x = fade(x);
+        let fade_y = fade(y);
+
+        let bottom = lerp(
+            gradient(bottom_left, x, y),
+            gradient(bottom_right, x - 1.0, y),
+            fade_x,
+        );
+        let top = lerp(
+            gradient(top_left, x, y - 1.0),
+            gradient(top_right, x - 1.0, y - 1.0),
+            fade_x,
+        );
+
+        lerp(bottom, top, fade_y)
+    }
+
+    /// Sums several layers of noise, each one with double the frequency
+    /// and `persistence` times the amplitude of the previous one.
+    /// The result is normalized back in the -1.0..1.0 range, without octaves it is 0.0
+    pub fn octaves(&self, x: f64, y: f64, octaves: u32, persistence: f64) -> f64 {
+        if octaves == 0 {
+            return 0.0;
+        }
+
+        let mut total = 0.0;
+        let mut max_value = 0.0;
+        let mut frequency = 1.0;
+        let mut amplitude = 1.0;
+
+        for _ in 0..octaves {
+            total += self.get(x * frequency, y * frequency) * amplitude;
+            max_value += amplitude;
+
+            frequency *= 2.0;
+            amplitude *= persistence;
+        }
+
+        total / max_value
+    }
+}
+
+/// The smootherstep curve used to ease the coordinates
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + t * (b - a)
+}
+
+/// Dot product between the offset vector and one of eight gradient directions
+fn gradient(hash: usize, x: f64, y: f64) -> f64 {
+    match hash & 7 {
+        0 => x + y,
+        1 => -x + y,
+        2 => x - y,
+        3 => -x - y,
+        4 => x,
+        5 => -x,
+        6 => y,
+        _ => -y,
+    }
+}
@@ -0,0 +1,136 @@
+use atlas::Sprite;
+use types::Color;
+#[cfg(feature = "window")]
+pub use self::system::ParticleSystem;
+
+/// Holds the information about the particles sprayed on a tile type
+pub struct ParticleSettings {
+    pub texture: Sprite,
+    // How many seconds each particle lives
+    pub lifetime: f32,
+    pub color: Color,
+    // The number of particles emitted each time
+    pub count: u32,
+}
+
+/// The particles drawn in the window
+#[cfg(feature = "window")]
+mod system {
+    use atlas::AtlasBatch;
+    use camera::Camera;
+    use game::TileType;
+    use ggez::Context;
+    use ggez::GameResult;
+    use ggez::graphics;
+    use ggez::graphics::{Image, Point2, Vector2};
+    use rand;
+    use rand::Rng;
+
+    struct Particle {
+        // The tile type the particle was sprayed from
+        tile_id: usize,
+        position: Vector2,
+        velocity: Vector2,
+        age: f32,
+    }
+
+    /// Holds all the living particles, in world space
+    pub struct ParticleSystem {
+        particles: Vec<Particle>,
+        batch: AtlasBatch,
+    }
+
+    impl ParticleSystem {
+        /// Creates the system, drawing from the given atlas pages
+        pub fn new(atlas_pages: &[Image]) -> ParticleSystem {
+            ParticleSystem {
+                particles: Vec::new(),
+                batch: AtlasBatch::new(atlas_pages),
+            }
+        }
+
+        /// Sprays `count` times the tile particles from the position.
+        /// The particles move along the direction, with some random spread
+        pub fn emit(
+            &mut self,
+            tile_types: &[TileType],
+            tile_id: usize,
+            position: Vector2,
+            direction: Vector2,
+            count: u32,
+        ) {
+            let settings = match tile_types[tile_id].particles {
+                Some(ref settings) => settings,
+                None => return,
+            };
+
+            let mut rng = rand::thread_rng();
+            for _ in 0..settings.count * count {
+                let spread = Vector2::new(rng.gen_range(-1.0, 1.0), rng.gen_range(-1.0, 1.0));
+                self.particles.push(Particle {
+                    tile_id,
+                    position,
+                    velocity: direction * rng.gen_range(0.5, 1.0) + spread,
+                    age: 0.0,
+                });
+            }
+        }
+
+        pub fn update(&mut self, tile_types: &[TileType], dt: f32) {
+            for particle in &mut self.particles {
+                particle.position += particle.velocity * dt;
+                // The air slows the particles down
+                particle.velocity -= particle.velocity * 3.0 * dt;
+                particle.age += dt;
+            }
+
+            self.particles.retain(|particle| {
+                let settings = tile_types[particle.tile_id].particles.as_ref().unwrap();
+                particle.age < settings.lifetime
+            });
+        }
+
+        /// Moves the particles together with the world
+        pub fn scroll(&mut self, scrolling: u32) {
+            for particle in &mut self.particles {
+                particle.position.y -= scrolling as f32;
+            }
+        }
+
+        pub fn clear(&mut self) {
+            self.particles.clear();
+        }
+
+        /// Draws the particles as seen by the camera, with a single batch for each atlas page.
+        /// Returns the number of draw calls issued
+        pub fn draw(
+            &mut self,
+            ctx: &mut Context,
+            tile_types: &[TileType],
+            camera: &Camera,
+        ) -> GameResult<u32> {
+            self.batch.clear();
+
+            let scale = camera.scale();
+            for particle in &self.particles {
+                let settings = tile_types[particle.tile_id].particles.as_ref().unwrap();
+                // The particles fade away while they get older
+                let mut color = settings.color;
+                color.a *= 1.0 - particle.age / settings.lifetime;
+
+                self.batch.add(
+                    &settings.texture,
+                    graphics::DrawParam {
+                        dest: camera.world_to_screen(&particle.position),
+                        scale: Point2::new(scale, scale),
+                        offset: Point2::new(0.5, 0.5),
+                        color: Some(color),
+                        ..Default::default()
+                    },
+                );
+            }
+
+            self.batch.draw(ctx, Default::default())
+        }
+    }
+}
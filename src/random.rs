@@ -0,0 +1,37 @@
+use rand;
+use rand::Rng;
+
+/// A small random number generator (xorshift64*) whose state can be saved,
+/// so that the same seed always produces the same slope
+#[derive(Clone, Debug)]
+pub struct SeededRng {
+    pub state: u64,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> SeededRng {
+        // The state must never be zero, otherwise it would stay zero forever
+        let state = seed ^ 0x9E37_79B9_7F4A_7C15;
+        SeededRng {
+            state: if state == 0 { 1 } else { state },
+        }
+    }
+}
+
+/// Returns a new seed, different on every call
+pub fn random_seed() -> u64 {
+    rand::random()
+}
+
+impl Rng for SeededRng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+}
@@ -1,61 +1,546 @@
-use std::time::Duration;
-use game::World;
+use std::io;
+use std::io::prelude::*;
+use std::mem;
+use std::path::PathBuf;
+use animation::player_state;
+use atlas::{Atlas, AtlasBatch, Sprite};
+use camera::Camera;
+use capture::{Capture, CaptureSettings};
+use game::{
+    Object, PlayerInput, World, DEFAULT_SLOPE_WIDTH, MIN_SLOPE_WIDTH, SCROLL_Y, TRAIL_COLOR,
+};
+use generation::{generator_from_name, with_segments, Generator};
+use input::{button_key, Action, InputSettings, InputState};
+use loader::{load_resources, user_file, Pack};
+use map::Map;
+use particles::ParticleSystem;
+use random::random_seed;
+use replay::{pack_versions, Replay};
+use simulation::Controller;
+use states::{MainMenu, State, Transition};
 
 use ggez::event;
-use ggez::{Context, GameResult};
+use ggez::{Context, GameError, GameResult};
 use ggez::graphics;
-use ggez::graphics::{Point2, Vector2};
-use ggez::event::{Keycode, Mod};
+use ggez::graphics::{Image, Point2, Vector2};
+use ggez::event::{Axis, Button, Keycode, Mod, MouseButton, MouseState};
 use ggez::timer;
+use image::RgbaImage;
+use serde_json;
+use serde_json::Value;
+
+// The sideways speed over which the skies spray snow
+const SKID_SPEED: f32 = 1.0;
+// The file holding the run saved to be continued, in the user config directory
+const SAVE_PATH: &str = "/save.json";
+// The directory holding the exported maps, in the user data directory
+pub const MAPS_DIR: &str = "maps";
 
 pub struct ViewState {
     pub world: World,
     pub dead: bool,
+    pub particles: ParticleSystem,
 
     pub window_size: (u32, u32),
-    pub offset_y: f32,
-    pub scale: f32,
+    pub camera: Camera,
+    // The number of tiles across the slope, applied when the world is reset
+    pub slope_width: u32,
 
-    pub pressed_keys: Vec<Keycode>,
+    // The keys bound to each action, and what is pressed right now
+    pub input: InputSettings,
+    pub input_state: InputState,
 
     pub font: graphics::Font,
+    // The textures packed by the loader, and their images on the screen
+    pub atlas: Atlas,
+    pub atlas_pages: Vec<Image>,
+
+    // Draw tiles and objects with a batch each, instead of a call for each sprite
+    pub batched: bool,
+    // The number of draw calls issued in the last frame
+    pub draw_calls: u32,
+    tile_batch: AtlasBatch,
+    object_batch: AtlasBatch,
+    // Set when the tiles or objects changed since the batches were built
+    batches_dirty: bool,
+
+    // The seconds passed since the game started, used by the animations
+    pub animation_time: f32,
+    // The animation state of the player, and when it started
+    player_state: &'static str,
+    player_state_start: f32,
+    // Animated objects change every frame, so their batch cannot be kept
+    objects_animated: bool,
+
+    // The menus and screens, only the last one is updated and drawn
+    states: Vec<Box<State>>,
+    // The name of the mode being played, see `select_mode`
+    pub mode: String,
+    // The generator from the config files, kept aside while another mode is played
+    pack_generator: Option<Box<Generator>>,
+
+    // The seed of every run, a random one is chosen for each run when missing
+    pub seed: Option<u64>,
+    // The distance every run starts from
+    pub start: u32,
+    // The inputs of the current run, saved in `record_path` when the player crashes
+    pub run: Replay,
+    pub record_path: Option<PathBuf>,
+    // The name and version of the packs loaded, stored with each run
+    packs: Vec<(String, String)>,
+    // The run being played back instead of the player input, and its next update
+    playback: Option<Replay>,
+    playback_tick: usize,
+    // Skis in place of the player, eg: behind the main menu
+    pub autopilot: Option<Box<Controller>>,
+    // The map every run starts on, instead of a generated slope
+    pub map: Option<Map>,
+    // Takes the screenshots and recordings
+    pub capture: Capture,
+    // Whether the next frame drawn is saved as a screenshot, or added to the recording
+    screenshot_requested: bool,
+    record_frame: bool,
+    // Whether there is a saved run to continue
+    pub has_saved_game: bool,
 }
 
 impl ViewState {
-    pub fn new(ctx: &mut Context) -> GameResult<ViewState> {
-        let window_size = (ctx.conf.window_mode.width, ctx.conf.window_mode.height);
+    /// Creates the view, loading the asset packs layered in order
+    pub fn new(ctx: &mut Context, packs: &[Pack]) -> GameResult<ViewState> {
+        // A fullscreen window is as big as the screen, not as the configured size
+        let window_size = graphics::get_size(ctx);
+        graphics::set_screen_coordinates(
+            ctx,
+            graphics::Rect::new(0.0, 0.0, window_size.0 as f32, window_size.1 as f32),
+        )?;
 
         graphics::set_background_color(ctx, graphics::WHITE);
 
-        let mut world = World::new(ctx);
-        world.reset(7, 16);
+        let (resources, atlas) = load_resources(packs);
+        let atlas_pages = atlas.upload(ctx)?;
+        let tile_batch = AtlasBatch::new(&atlas_pages);
+        let object_batch = AtlasBatch::new(&atlas_pages);
+        let particles = ParticleSystem::new(&atlas_pages);
+
+        let mut world = World::new(resources);
+        world.reset(DEFAULT_SLOPE_WIDTH, 16);
+        let objects_animated = world
+            .object_types
+            .iter()
+            .any(|object_type| !object_type.animations.is_empty());
 
         let font = graphics::Font::new(ctx, "/fonts/DejaVuSerif.ttf", 16)?;
 
-        Ok(ViewState {
+        let mut state = ViewState {
             world,
             dead: false,
+            particles,
             window_size,
-            offset_y: 2.0,
-            scale: 5.0,
-            pressed_keys: Vec::new(),
+            camera: Camera::new(window_size, 5.0),
+            slope_width: DEFAULT_SLOPE_WIDTH,
+            input: InputSettings::load(ctx),
+            input_state: InputState::new(),
             font,
-        })
+            atlas,
+            atlas_pages,
+            batched: true,
+            draw_calls: 0,
+            tile_batch,
+            object_batch,
+            batches_dirty: true,
+            animation_time: 0.0,
+            player_state: "straight",
+            player_state_start: 0.0,
+            objects_animated,
+            states: vec![Box::new(MainMenu::new())],
+            mode: "classic".to_owned(),
+            pack_generator: None,
+            seed: None,
+            start: 0,
+            run: Replay::new(0, "classic", DEFAULT_SLOPE_WIDTH, 0, 0),
+            record_path: None,
+            packs: pack_versions(packs),
+            playback: None,
+            playback_tick: 0,
+            autopilot: None,
+            map: None,
+            capture: Capture::new(CaptureSettings::new()),
+            screenshot_requested: false,
+            record_frame: false,
+            has_saved_game: ctx.filesystem.exists(SAVE_PATH),
+        };
+        state.fit_window();
+        state.camera.snap(&state.world);
+
+        Ok(state)
     }
 
-    /// Returns a point from world space to screen space
-    pub fn get_screen_point(&self, point: &Vector2) -> Point2 {
-        Point2::new(
-            (point.x - self.world.width() as f32 / 2.0) * self.scale * 16.0
-                + self.window_size.0 as f32 / 2.0,
-            (-point.y + self.world.player.position.y - self.offset_y) * self.scale * 16.0
-                + self.window_size.1 as f32,
-        )
+    /// Chooses the scale so that the slope fills the window without being stretched,
+    /// and generates enough rows to cover the visible area
+    pub fn fit_window(&mut self) {
+        let slope_width = self.world.width() as u32;
+        let rows = self.fit_scale(slope_width);
+        self.world.resize_height(rows as usize);
+        self.batches_dirty = true;
+    }
+
+    /// Chooses the scale for a slope `slope_width` tiles wide,
+    /// returns the number of rows needed to cover the visible area
+    fn fit_scale(&mut self, slope_width: u32) -> u32 {
+        self.camera.fit(self.window_size, slope_width);
+
+        // The player moves up to SCROLL_Y before the world scrolls,
+        // showing the rows above the screen top
+        let visible_rows = self.camera.max_visible_rows();
+        (visible_rows.ceil() + (SCROLL_Y - self.camera.offset_y).ceil() + 2.0) as u32
+    }
+
+    /// Changes the number of tiles across the slope and starts a new run on it
+    pub fn set_slope_width(&mut self, width: u32) {
+        self.slope_width = width.max(MIN_SLOPE_WIDTH);
+        self.restart();
+    }
+
+    /// Starts a new run, on a slope `slope_width` tiles wide.
+    /// While playing back a run, it starts again with the settings of that run
+    pub fn restart(&mut self) {
+        self.dead = false;
+        self.playback_tick = 0;
+        if let Some(ref mut autopilot) = self.autopilot {
+            autopilot.reset();
+        }
+
+        let (seed, slope_width, start) = match self.playback {
+            Some(ref replay) => (replay.seed, replay.slope_width, replay.start),
+            None => (
+                self.seed.unwrap_or_else(random_seed),
+                self.map
+                    .as_ref()
+                    .map_or(self.slope_width, |map| map.width() as u32),
+                self.start,
+            ),
+        };
+        // Whatever asked for it, a narrower slope cannot be generated
+        let slope_width = slope_width.max(MIN_SLOPE_WIDTH);
+        let rows = self.fit_scale(slope_width);
+        // A run played back keeps its rows, so that the same slope is generated
+        let height = self.playback.as_ref().map_or(rows, |replay| replay.height);
+        self.world.reset_seeded(slope_width, height, seed);
+        if let (Some(map), None) = (self.map.as_ref(), self.playback.as_ref()) {
+            self.world
+                .load_map(map)
+                .expect("The map was checked when it was chosen");
+        }
+        self.world.advance(start);
+        self.run = Replay::new(seed, &self.mode, slope_width, height, start);
+        self.run.packs = self.packs.clone();
+
+        self.particles.clear();
+        self.batches_dirty = true;
+        self.camera.snap(&self.world);
+    }
+
+    /// Plays the run back from the beginning, instead of following the player input
+    pub fn play_replay(&mut self, replay: Replay) {
+        let mode = replay.mode.clone();
+        self.playback = Some(replay);
+        // Selecting the mode restarts the run
+        self.select_mode(&mode);
+    }
+
+    /// Starts every run on the map, until another mode is selected
+    pub fn play_map(&mut self, map: Map) -> io::Result<()> {
+        // Checks that the map only uses known types
+        self.world.load_map(&map)?;
+
+        self.playback = None;
+        self.map = Some(map);
+        self.restart();
+        Ok(())
+    }
+
+    /// Saves the rows and objects on the screen in the user data directory
+    pub fn export_map(&self) {
+        let path = user_file(MAPS_DIR, "map", "json");
+        match path.and_then(|path| Map::from_world(&self.world).save(&path).map(|_| path)) {
+            Ok(path) => println!("Saved the map in {}", path.display()),
+            Err(error) => eprintln!("Could not save the map: {}", error),
+        }
+    }
+
+    /// Returns the input of the player, of the run being played back or of the autopilot
+    fn next_input(&mut self) -> PlayerInput {
+        if let Some(ref replay) = self.playback {
+            self.playback_tick += 1;
+            return replay.input(self.playback_tick - 1);
+        }
+        if let Some(ref mut autopilot) = self.autopilot {
+            return autopilot.input(&self.world);
+        }
+
+        PlayerInput {
+            steering: self.input_state.steering(&self.input),
+            tucking: self.input_state.is_active(Action::Tuck, &self.input),
+            braking: self.input_state.is_active(Action::Brake, &self.input),
+        }
     }
 
+    /// Moves the player with the pressed keys and advances the world by `dt` seconds
+    pub fn update_world(&mut self, dt: f32) {
+        if !self.dead {
+            let input = self.next_input();
+            self.run.inputs.push(input);
+
+            let (scrolling, crashed) = self.world.step(&input, dt);
+            if scrolling > 0 {
+                self.particles.scroll(scrolling);
+                self.camera.scroll(scrolling);
+                self.batches_dirty = true;
+            }
+
+            self.dead = crashed;
+            self.emit_particles(crashed);
+            if crashed {
+                self.camera.shake(1.0);
+                self.save_run();
+            }
+        }
+        self.particles.update(&self.world.tile_types, dt);
+        self.camera.follow(&self.world, dt);
+
+        self.animation_time += dt;
+        let player_state = player_state(&self.world.player, self.dead);
+        if player_state != self.player_state {
+            self.player_state = player_state;
+            self.player_state_start = self.animation_time;
+        }
+    }
+
+    /// Saves the inputs of the current run, when recording
+    fn save_run(&self) {
+        // The runs of the autopilot would replace the one of the player,
+        // and the runs on a map cannot be played back
+        if self.autopilot.is_some() || self.map.is_some() {
+            return;
+        }
+        if let Some(ref path) = self.record_path {
+            if let Err(error) = self.run.save(path) {
+                eprintln!("Could not save the run: {}", error);
+            }
+        }
+    }
+
+    /// Saves the current run, so that it can be continued after quitting
+    pub fn save_game(&mut self, ctx: &mut Context) -> GameResult<()> {
+        let json = json!({
+            "run": self.run.to_json(),
+            "world": self.world.save_state(),
+        });
+
+        let mut file = ctx.filesystem.create(SAVE_PATH)?;
+        file.write_all(serde_json::to_string(&json).unwrap().as_bytes())?;
+        self.has_saved_game = true;
+
+        Ok(())
+    }
+
+    /// Continues the saved run with the player at the controls.
+    /// Once loaded the save is removed, so that the run can be continued only once
+    pub fn load_game(&mut self, ctx: &mut Context) -> GameResult<()> {
+        let mut json_src = String::new();
+        ctx.filesystem
+            .open(SAVE_PATH)?
+            .read_to_string(&mut json_src)?;
+
+        let json: Value = serde_json::from_str(&json_src).map_err(io::Error::from)?;
+        let run = Replay::from_json(&json["run"])?;
+        if run.mode != "classic" && generator_from_name(&run.mode).is_none() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Unknown mode").into());
+        }
+
+        // The save is checked before the mode changes, so a bad save leaves the game as it was
+        let state = self.world.read_state(&json["world"])?;
+        self.autopilot = None;
+        self.playback = None;
+        // Selecting the mode sets up its generator, then the world continues from the save
+        self.select_mode(&run.mode);
+        self.world.restore_state(state);
+        ctx.filesystem.delete(SAVE_PATH)?;
+        self.has_saved_game = false;
+        self.run = run;
+        self.dead = false;
+
+        // The saved slope may not be as wide as the current one
+        let slope_width = self.world.width() as u32;
+        self.fit_scale(slope_width);
+        self.particles.clear();
+        self.batches_dirty = true;
+        self.camera.snap(&self.world);
+
+        Ok(())
+    }
+
+    /// Draws the tiles, the trail, the objects, the particles and the player
+    pub fn draw_world(&mut self, ctx: &mut Context) -> GameResult<()> {
+        if self.batched {
+            if self.batches_dirty {
+                self.build_tile_batch();
+            }
+            if self.batches_dirty || self.objects_animated {
+                self.build_object_batch();
+            }
+            self.batches_dirty = false;
+
+            // The batches are relative to the world origin, and unscaled
+            let scale = self.camera.scale();
+            let origin = graphics::DrawParam {
+                dest: self.camera.world_to_screen(&Vector2::new(0.0, 0.0)),
+                scale: Point2::new(scale, scale),
+                ..Default::default()
+            };
+            self.draw_calls += self.tile_batch.draw(ctx, origin)?;
+            self.draw_trail(ctx)?;
+            self.draw_calls += self.object_batch.draw(ctx, origin)?;
+        } else {
+            self.draw_sprites(ctx)?;
+        }
+
+        self.draw_calls += self.particles
+            .draw(ctx, &self.world.tile_types, &self.camera)?;
+
+        // Draw the player
+        let position = self.camera.world_to_screen(&self.world.player.position);
+        let texture = self.player_frame();
+        let rotation = self.world.player.rotation;
+        self.draw_sprite(
+            ctx,
+            &texture,
+            graphics::DrawParam {
+                dest: position,
+                scale: Point2::new(self.camera.scale(), self.camera.scale()),
+                rotation,
+                offset: Point2::new(texture.width as f32 / 32.0, texture.height as f32 / 32.0),
+                ..Default::default()
+            },
+        )?;
+
+        Ok(())
+    }
+
+    /// Saves a picture of the screen in the user data directory, once the next frame is drawn
+    pub fn screenshot(&mut self) {
+        self.screenshot_requested = true;
+    }
+
+    /// Starts recording the screen in the user data directory, or stops the recording
+    pub fn toggle_recording(&mut self) {
+        let recording = self.capture.is_recording();
+        match self.capture.toggle_recording() {
+            Ok(ref path) if recording => {
+                self.record_frame = false;
+                println!("Saved the recording in {}", path.display())
+            }
+            Ok(_) => println!("Recording"),
+            Err(ref error) if recording => eprintln!("Could not save the recording: {}", error),
+            Err(error) => eprintln!("Could not start the recording: {}", error),
+        }
+    }
+
+    /// Counts the update while recording: unless it is skipped, the next frame drawn is recorded
+    fn record_update(&mut self) {
+        if self.capture.next_update() {
+            self.record_frame = true;
+        }
+    }
+
+    /// Saves the frame drawn so far as requested, as a screenshot or in the recording.
+    /// Only the pixels are read here, the frames are encoded by another thread
+    fn capture_frame(&mut self, ctx: &mut Context) {
+        let screenshot = mem::replace(&mut self.screenshot_requested, false);
+        let record = mem::replace(&mut self.record_frame, false);
+        if !screenshot && !record {
+            return;
+        }
+
+        let picture = match drawn_picture(ctx) {
+            Ok(picture) => picture,
+            Err(error) => {
+                eprintln!("Could not capture the screen: {}", error);
+                return;
+            }
+        };
+        if screenshot {
+            match self.capture.screenshot(&picture) {
+                Ok(path) => println!("Saved the screenshot in {}", path.display()),
+                Err(error) => eprintln!("Could not save the screenshot: {}", error),
+            }
+        }
+        // The recording is already stopped when a frame could not be written
+        if record {
+            if let Err(error) = self.capture.record(picture) {
+                eprintln!("Could not record the screen: {}", error);
+            }
+        }
+    }
+
+    /// Replaces the states with the given one
+    pub fn set_state(&mut self, state: Box<State>) {
+        self.states.clear();
+        self.states.push(state);
+    }
+
+    /// Switches to the generator of the mode, and starts a new run with it.
+    /// "classic" is the generator described by the config files
+    pub fn select_mode(&mut self, mode: &str) {
+        // The map is left for the generated slopes
+        self.map = None;
+        if mode != self.mode {
+            let generator = if mode == "classic" {
+                self.pack_generator.take().expect("The classic generator is in use")
+            } else {
+                let generator = generator_from_name(mode).expect("Unknown mode");
+                with_segments(generator, self.world.segments.clone())
+            };
+
+            let previous = self.world.replace_generator(generator);
+            // Only the config generator cannot be created again from its name
+            if self.mode == "classic" {
+                self.pack_generator = Some(previous);
+            }
+            self.mode = mode.to_owned();
+        }
+
+        self.restart();
+    }
+
+    /// Runs the function with the current state taken out of the stack,
+    /// so that the state can modify the view
+    fn with_state<F>(&mut self, ctx: &mut Context, function: F) -> GameResult<()>
+    where
+        F: FnOnce(&mut State, &mut ViewState, &mut Context) -> GameResult<Transition>,
+    {
+        let mut state = self.states.pop().expect("There is no state");
+        let result = function(&mut *state, self, ctx);
+        self.states.push(state);
+
+        match result? {
+            Transition::None => {}
+            Transition::Push(state) => self.states.push(state),
+            Transition::Pop => {
+                self.states.pop();
+            }
+            Transition::Switch(state) => {
+                self.states.pop();
+                self.states.push(state);
+            }
+            Transition::Quit => ctx.quit()?,
+        }
+
+        Ok(())
+    }
 
     /// Draws a text with the currently loaded font
-    fn draw_text(
+    pub fn draw_text(
         &mut self,
         ctx: &mut Context,
         content: &str,
@@ -78,12 +563,202 @@ impl ViewState {
                 ..Default::default()
             },
         )?;
+        self.draw_calls += 1;
+
+        Ok(())
+    }
+
+    /// Draws the marks left by the skies, a line for each ski
+    fn draw_trail(&mut self, ctx: &mut Context) -> GameResult<()> {
+        for points in self.world.trail.windows(2) {
+            let (from, to) = (&points[0], &points[1]);
+            // Points far apart are not part of the same movement
+            if (to.position - from.position).norm() > 1.0 {
+                continue;
+            }
+
+            let color = graphics::Color::new(
+                TRAIL_COLOR[0],
+                TRAIL_COLOR[1],
+                TRAIL_COLOR[2],
+                to.intensity,
+            );
+            graphics::set_color(ctx, color)?;
+
+            let (from_left, from_right) = from.skies();
+            let (to_left, to_right) = to.skies();
+            for &(ref from_ski, ref to_ski) in &[(from_left, to_left), (from_right, to_right)] {
+                let points = [
+                    self.camera.world_to_screen(from_ski),
+                    self.camera.world_to_screen(to_ski),
+                ];
+                graphics::line(ctx, &points, self.camera.scale())?;
+                self.draw_calls += 1;
+            }
+        }
 
+        // Images use the current color too
+        graphics::set_color(ctx, graphics::WHITE)?;
         Ok(())
     }
 
+    /// Fills the tile batch, with positions relative to the world origin and unscaled,
+    /// the camera transform is applied when drawing it.
+    /// Tiles only move when the world scrolls, so only then it is rebuilt
+    fn build_tile_batch(&mut self) {
+        self.tile_batch.clear();
+        for y in 0..self.world.height() {
+            for x in 0..self.world.width() {
+                let tile_type = &self.world.tile_types[self.world.tiles[y][x]];
+                self.tile_batch.add(
+                    &tile_type.texture,
+                    graphics::DrawParam {
+                        dest: Point2::new(x as f32 * 16.0, -(y as f32) * 16.0),
+                        ..Default::default()
+                    },
+                );
+            }
+        }
+    }
+
+    /// Fills the object batch, with positions relative to the world origin and unscaled
+    fn build_object_batch(&mut self) {
+        self.object_batch.clear();
+        for &(ref object_id, ref object) in &self.world.objects {
+            let texture = self.object_frame(*object_id, object);
+            self.object_batch.add(
+                &texture,
+                graphics::DrawParam {
+                    dest: Point2::new(object.position.x * 16.0, -object.position.y * 16.0),
+                    rotation: object.rotation,
+                    offset: Point2::new(
+                        texture.width as f32 / 32.0,
+                        texture.height as f32 / 32.0,
+                    ),
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
+    /// Returns the current frame of the player
+    fn player_frame(&self) -> Sprite {
+        let player_type = &self.world.player_type;
+        player_type.animations.frame_or(
+            self.player_state,
+            self.animation_time - self.player_state_start,
+            player_type.texture,
+        )
+    }
+
+    /// Returns the current frame of the object
+    fn object_frame(&self, object_id: usize, object: &Object) -> Sprite {
+        let object_type = &self.world.object_types[object_id];
+        // Each column is a bit out of step, so that objects do not move all together
+        let time = self.animation_time + object.position.x * 0.3;
+        object_type
+            .animations
+            .frame_or("idle", time, object_type.texture)
+    }
+
+    /// Draws tiles, trail and objects with a call for each sprite
+    fn draw_sprites(&mut self, ctx: &mut Context) -> GameResult<()> {
+        // Draw the tiles
+        for x in 0..self.world.width() {
+            for y in 0..self.world.height() {
+                let tile_id = self.world.tiles[y][x];
+                let texture = self.world.tile_types[tile_id].texture;
+                let position = self.camera.world_to_screen(&Vector2::new(x as f32, y as f32));
+                self.draw_sprite(
+                    ctx,
+                    &texture,
+                    graphics::DrawParam {
+                        dest: position,
+                        scale: Point2::new(self.camera.scale(), self.camera.scale()),
+                        ..Default::default()
+                    },
+                )?;
+            }
+        }
+
+        self.draw_trail(ctx)?;
+
+        // Draw the objects
+        for i in 0..self.world.objects.len() {
+            let (texture, position, rotation) = {
+                let &(object_id, ref object) = &self.world.objects[i];
+                (
+                    self.object_frame(object_id, object),
+                    object.position,
+                    object.rotation,
+                )
+            };
+            let position = self.camera.world_to_screen(&position);
+            self.draw_sprite(
+                ctx,
+                &texture,
+                graphics::DrawParam {
+                    dest: position,
+                    scale: Point2::new(self.camera.scale(), self.camera.scale()),
+                    rotation,
+                    offset: Point2::new(
+                        texture.width as f32 / 32.0,
+                        texture.height as f32 / 32.0,
+                    ),
+                    ..Default::default()
+                },
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Draws a single sprite from its atlas page
+    pub fn draw_sprite(
+        &mut self,
+        ctx: &mut Context,
+        sprite: &Sprite,
+        param: graphics::DrawParam,
+    ) -> GameResult<()> {
+        graphics::draw_ex(
+            ctx,
+            &self.atlas_pages[sprite.page],
+            graphics::DrawParam {
+                src: sprite.region,
+                ..param
+            },
+        )?;
+        self.draw_calls += 1;
+
+        Ok(())
+    }
+
+    /// Sprays the snow under the player when skidding or crashing
+    fn emit_particles(&mut self, crashed: bool) {
+        let tile_under = self.world.tile_under_player();
+        let position = self.world.player.position;
+
+        if crashed {
+            // The snow flies all around the player
+            let direction = self.world.player.velocity * 0.5;
+            self.particles
+                .emit(&self.world.tile_types, tile_under, position, direction, 10);
+        } else {
+            let sideways_velocity = self.world.player.sideways_velocity();
+            if sideways_velocity.norm() > SKID_SPEED {
+                self.particles.emit(
+                    &self.world.tile_types,
+                    tile_under,
+                    position,
+                    sideways_velocity,
+                    1,
+                );
+            }
+        }
+    }
+
     /// Draws the screen ui for when the player is alive
-    fn draw_alive_ui(&mut self, ctx: &mut Context) -> GameResult<()> {
+    pub fn draw_alive_ui(&mut self, ctx: &mut Context) -> GameResult<()> {
         let content = format!(
             "{:.1} meters",
             self.world.real_y + self.world.player.position.y
@@ -95,7 +770,7 @@ impl ViewState {
     }
 
     /// Draws the screen ui for when the player is dead
-    fn draw_dead_ui(&mut self, ctx: &mut Context) -> GameResult<()> {
+    pub fn draw_dead_ui(&mut self, ctx: &mut Context) -> GameResult<()> {
         let content = format!(
             "You crashed after {:.2} meters! How unfortunate!",
             self.world.real_y + self.world.player.position.y
@@ -113,143 +788,136 @@ impl ViewState {
         );
         self.draw_text(ctx, content, position, graphics::BLACK)?;
 
-        let content = "Press Enter to restart";
-        let position = graphics::Point2::new(
-            self.window_size.0 as f32 / 2.0,
-            self.window_size.1 as f32 / 2.0 + 60.0,
-        );
-        self.draw_text(ctx, content, position, graphics::BLACK)?;
-
         Ok(())
     }
 }
 
+/// Reads back the pixels drawn on the screen so far, with the particles and the menus
+fn drawn_picture(ctx: &mut Context) -> GameResult<RgbaImage> {
+    let image = graphics::screenshot(ctx)?;
+    let pixels = image.to_rgba8(ctx)?;
+    RgbaImage::from_raw(image.width(), image.height(), pixels)
+        .ok_or_else(|| GameError::RenderError("The screenshot has the wrong size".to_owned()))
+}
+
 impl event::EventHandler for ViewState {
     fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
         const DESIRED_FPS: u32 = 60;
         while timer::check_update_time(ctx, DESIRED_FPS) {
-            let dt = to_seconds(&timer::get_delta(ctx));
-
-            let mut steering_direction = 0.0;
-            if self.pressed_keys.contains(&Keycode::Right) {
-                steering_direction += 1.0;
-            }
-            if self.pressed_keys.contains(&Keycode::Left) {
-                steering_direction -= 1.0;
-            }
-
-            // While turning the player slowly decreses the turning speed
-            // When switching turning directions the player steers faster
-            self.world.player.angular_velocity +=
-                (steering_direction * 15.0 - self.world.player.angular_velocity * 0.2) * dt as f32;
-
-            // Generate a new portion of map
-            if self.world.player.position.y > 6.0 {
-                self.world.scroll((6.0 - self.offset_y) as u32);
-            }
-
-            if !self.dead {
-                self.dead = self.world.update(dt as f32);
-            }
+            // A fixed time step, so that the runs can be played back exactly
+            let dt = 1.0 / DESIRED_FPS as f32;
+            self.with_state(ctx, |state, view, _| Ok(state.update(view, dt)))?;
+            self.record_update();
         }
         Ok(())
     }
 
-    fn key_down_event(&mut self, _ctx: &mut Context, keycode: Keycode, _keymod: Mod, repeat: bool) {
-        if self.dead {
-            // Restart the game
-            if let Keycode::Return = keycode {
-                self.dead = false;
-                let width = self.world.width() as u32;
-                let height = self.world.height() as u32;
-                self.world.reset(width, height);
+    fn key_down_event(&mut self, ctx: &mut Context, keycode: Keycode, _keymod: Mod, repeat: bool) {
+        if !repeat {
+            // The pictures can be taken from every screen,
+            // their keys are not seen by the states, eg: bound again by the controls
+            match self.input.action(keycode) {
+                Some(Action::Screenshot) => return self.screenshot(),
+                Some(Action::Record) => return self.toggle_recording(),
+                Some(Action::ExportMap) => return self.export_map(),
+                _ => {}
             }
-        }
 
-        if !repeat {
             // Add the key to the pressed keys
-            self.pressed_keys.push(keycode);
+            self.input_state.key_down(keycode);
+            self.with_state(ctx, |state, view, ctx| Ok(state.key_down(view, ctx, keycode)))
+                .unwrap();
         }
     }
     fn key_up_event(&mut self, _ctx: &mut Context, keycode: Keycode, _keymod: Mod, repeat: bool) {
         if !repeat {
             // Remove the key from the pressed keys
-            for i in 0..self.pressed_keys.len() {
-                if self.pressed_keys[i] == keycode {
-                    self.pressed_keys.remove(i);
-                    break;
-                }
-            }
+            self.input_state.key_up(keycode);
         }
     }
 
-    fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
-        graphics::clear(ctx);
+    fn mouse_button_down_event(&mut self, ctx: &mut Context, button: MouseButton, x: i32, y: i32) {
+        let position = Point2::new(x as f32, y as f32);
+        self.with_state(ctx, |state, view, _| Ok(state.mouse_down(view, button, position)))
+            .unwrap();
+    }
 
-        // Draw the tiles
-        for x in 0..self.world.width() {
-            for y in 0..self.world.height() {
-                let tile_id = self.world.tiles[y][x];
-                let tile_type = &self.world.tile_types[tile_id];
-                let position = self.get_screen_point(&Vector2::new(x as f32, y as f32));
-                graphics::draw_ex(
-                    ctx,
-                    &tile_type.texture,
-                    graphics::DrawParam {
-                        dest: position,
-                        scale: Point2::new(self.scale, self.scale),
-                        ..Default::default()
-                    },
-                )?;
-            }
+    fn mouse_button_up_event(&mut self, ctx: &mut Context, button: MouseButton, _x: i32, _y: i32) {
+        self.with_state(ctx, |state, view, _| Ok(state.mouse_up(view, button)))
+            .unwrap();
+    }
+
+    fn mouse_motion_event(
+        &mut self,
+        ctx: &mut Context,
+        _state: MouseState,
+        x: i32,
+        y: i32,
+        _xrel: i32,
+        _yrel: i32,
+    ) {
+        let position = Point2::new(x as f32, y as f32);
+        self.with_state(ctx, |state, view, _| Ok(state.mouse_motion(view, position)))
+            .unwrap();
+    }
+
+    fn mouse_wheel_event(&mut self, ctx: &mut Context, _x: i32, y: i32) {
+        self.with_state(ctx, |state, view, _| Ok(state.mouse_wheel(view, y)))
+            .unwrap();
+    }
+
+    fn controller_button_down_event(&mut self, ctx: &mut Context, button: Button, _id: i32) {
+        self.input_state.button_down(button);
+        // The menus are navigated with the keys the buttons stand for
+        if let Some(keycode) = button_key(button, &self.input) {
+            self.with_state(ctx, |state, view, ctx| Ok(state.key_down(view, ctx, keycode)))
+                .unwrap();
         }
+    }
 
-        // Draw the objects
-        for &(ref object_id, ref object) in &self.world.objects {
-            let object_type = &self.world.object_types[*object_id];
-            let position = self.get_screen_point(&object.position);
-            let width = object_type.texture.width();
-            let height = object_type.texture.height();
-            graphics::draw_ex(
-                ctx,
-                &object_type.texture,
-                graphics::DrawParam {
-                    dest: position,
-                    scale: Point2::new(self.scale, self.scale),
-                    rotation: object.rotation as f32,
-                    offset: Point2::new(width as f32 / 32.0, height as f32 / 32.0),
-                    ..Default::default()
-                },
-            )?;
+    fn controller_button_up_event(&mut self, _ctx: &mut Context, button: Button, _id: i32) {
+        self.input_state.button_up(button);
+    }
+
+    fn controller_axis_event(&mut self, _ctx: &mut Context, axis: Axis, value: i16, _id: i32) {
+        self.input_state.axis(axis, value);
+    }
+
+    fn focus_event(&mut self, _ctx: &mut Context, gained: bool) {
+        // The keys released in another window would stay pressed
+        if !gained {
+            self.input_state.clear();
         }
+    }
 
-        // Draw the player
-        let position = self.get_screen_point(&self.world.player.position);
-        let width = self.world.player_type.texture.width();
-        let height = self.world.player_type.texture.height();
-        graphics::draw_ex(
+    fn resize_event(&mut self, ctx: &mut Context, width: u32, height: u32) {
+        // Keep one unit per pixel, otherwise everything would be stretched
+        graphics::set_screen_coordinates(
             ctx,
-            &self.world.player_type.texture,
-            graphics::DrawParam {
-                dest: position,
-                scale: Point2::new(self.scale, self.scale),
-                rotation: self.world.player.rotation as f32,
-                offset: Point2::new(width as f32 / 32.0, height as f32 / 32.0),
-                ..Default::default()
-            },
-        )?;
+            graphics::Rect::new(0.0, 0.0, width as f32, height as f32),
+        ).unwrap();
+        self.window_size = (width, height);
+        self.fit_window();
+    }
 
-        if self.dead {
-            self.draw_dead_ui(ctx)?;
-        } else {
-            self.draw_alive_ui(ctx)?;
+    fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
+        graphics::clear(ctx);
+        self.draw_calls = 0;
+
+        self.with_state(ctx, |state, view, ctx| {
+            state.draw(view, ctx)?;
+            Ok(Transition::None)
+        })?;
+
+        // The captures show the frame as presented, without the recording label
+        self.capture_frame(ctx);
+        if self.capture.is_recording() {
+            let position = Point2::new(50.0, 15.0);
+            let color = graphics::Color::new(0.9, 0.1, 0.1, 1.0);
+            self.draw_text(ctx, "Recording", position, color)?;
         }
 
         graphics::present(ctx);
         Ok(())
     }
 }
-
-fn to_seconds(elapsed: &Duration) -> f64 {
-    elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) * 1e-9
-}
@@ -0,0 +1,262 @@
+use serde_json;
+use serde_json::Value;
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use game::{PlayerInput, MIN_SLOPE_WIDTH};
+use loader::{invalid, Pack};
+
+/// Everything needed to play a run again: the slope settings,
+/// and the input of the player on each update.
+/// Resizing the window during a run changes the rows generated,
+/// so the replay of that run may differ from it
+pub struct Replay {
+    pub seed: u64,
+    pub mode: String,
+    pub slope_width: u32,
+    // The number of rows of the world when the run started
+    pub height: u32,
+    // The distance the run started from
+    pub start: u32,
+    // The name and version of the packs the run was played with, in the order they were layered
+    pub packs: Vec<(String, String)>,
+    pub inputs: Vec<PlayerInput>,
+}
+
+/// Returns the name and version of each pack, as stored in the replays
+pub fn pack_versions(packs: &[Pack]) -> Vec<(String, String)> {
+    packs
+        .iter()
+        .map(|pack| (pack.name.clone(), pack.version.clone()))
+        .collect()
+}
+
+impl Replay {
+    pub fn new(seed: u64, mode: &str, slope_width: u32, height: u32, start: u32) -> Replay {
+        Replay {
+            seed,
+            mode: mode.to_owned(),
+            slope_width,
+            height,
+            start,
+            packs: Vec::new(),
+            inputs: Vec::new(),
+        }
+    }
+
+    /// Describes how the packs differ from the ones the run was played with,
+    /// the replay may then differ from the run. The replays without packs are not checked
+    pub fn pack_mismatches(&self, packs: &[Pack]) -> Vec<String> {
+        let current = pack_versions(packs);
+        if self.packs.is_empty() || self.packs == current {
+            return Vec::new();
+        }
+
+        let mut mismatches = Vec::new();
+        for &(ref name, ref version) in &self.packs {
+            match current.iter().find(|&&(ref other, _)| other == name) {
+                Some(&(_, ref other_version)) if other_version != version => mismatches.push(
+                    format!("the pack {} is {}, the run used {}", name, other_version, version),
+                ),
+                Some(_) => {}
+                None => mismatches.push(format!("the pack {} {} is missing", name, version)),
+            }
+        }
+        for &(ref name, ref version) in &current {
+            if !self.packs.iter().any(|&(ref other, _)| other == name) {
+                mismatches.push(format!("the pack {} {} was not used by the run", name, version));
+            }
+        }
+        // The same packs, layered in another order
+        if mismatches.is_empty() {
+            mismatches.push("the packs are layered in another order than in the run".to_owned());
+        }
+
+        mismatches
+    }
+
+    /// Returns the input of the given update, the player does nothing after the last one
+    pub fn input(&self, tick: usize) -> PlayerInput {
+        self.inputs
+            .get(tick)
+            .cloned()
+            .unwrap_or_else(PlayerInput::none)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Replay> {
+        let mut json_src = String::new();
+        File::open(path)?.read_to_string(&mut json_src)?;
+        let json: Value = serde_json::from_str(&json_src)?;
+
+        Replay::from_json(&json)
+    }
+
+    pub fn from_json(json: &Value) -> io::Result<Replay> {
+        // A narrower slope cannot be generated
+        let slope_width = json["slope_width"]
+            .as_u64()
+            .filter(|&width| width >= u64::from(MIN_SLOPE_WIDTH))
+            .ok_or_else(|| invalid("The replay has no valid slope_width"))?;
+        let mut replay = Replay::new(
+            json["seed"]
+                .as_u64()
+                .ok_or_else(|| invalid("The replay has no valid seed"))?,
+            json["mode"]
+                .as_str()
+                .ok_or_else(|| invalid("The replay has no valid mode"))?,
+            slope_width as u32,
+            json["height"]
+                .as_u64()
+                .ok_or_else(|| invalid("The replay has no valid height"))? as u32,
+            json["start"].as_u64().unwrap_or(0) as u32,
+        );
+
+        // Missing in the replays recorded before the packs were stored
+        if let Some(packs) = json["packs"].as_array() {
+            for pack in packs {
+                match (pack["name"].as_str(), pack["version"].as_str()) {
+                    (Some(name), Some(version)) => {
+                        replay.packs.push((name.to_owned(), version.to_owned()))
+                    }
+                    _ => return Err(invalid("The replay has no valid packs")),
+                }
+            }
+        }
+
+        // Each input is stored as [steering, tucking, braking]
+        let inputs = json["inputs"]
+            .as_array()
+            .ok_or_else(|| invalid("The replay has no valid inputs"))?;
+        for input in inputs {
+            let (steering, tucking, braking) =
+                match (input[0].as_f64(), input[1].as_bool(), input[2].as_bool()) {
+                    (Some(steering), Some(tucking), Some(braking)) => (steering, tucking, braking),
+                    _ => return Err(invalid("The replay has no valid input")),
+                };
+            replay.inputs.push(PlayerInput {
+                steering: steering as f32,
+                tucking,
+                braking,
+            });
+        }
+
+        Ok(replay)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let file = File::create(path)?;
+        serde_json::to_writer(file, &self.to_json())?;
+
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> Value {
+        let inputs: Vec<Value> = self.inputs
+            .iter()
+            .map(|input| json!([input.steering, input.tucking, input.braking]))
+            .collect();
+        let packs: Vec<Value> = self.packs
+            .iter()
+            .map(|&(ref name, ref version)| json!({ "name": name, "version": version }))
+            .collect();
+        json!({
+            "seed": self.seed,
+            "mode": self.mode,
+            "slope_width": self.slope_width,
+            "height": self.height,
+            "start": self.start,
+            "packs": packs,
+            "inputs": inputs,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use autopilot::Autopilot;
+    use game::World;
+    use loader::base_resources;
+    use simulation::{new_world, Controller};
+
+    const DT: f32 = 1.0 / 60.0;
+
+    /// Starts the run of the replay on the world, as the game does
+    fn start(world: &mut World, replay: &Replay) {
+        world.reset_seeded(replay.slope_width, replay.height, replay.seed);
+        world.advance(replay.start);
+    }
+
+    #[test]
+    fn replay_reproduces_the_run() {
+        let mut world = new_world(base_resources(), "cellular", 7, 16);
+        let mut run = Replay::new(11, "cellular", 7, 16, 0);
+
+        // A previous run must not leak into the recorded one
+        world.reset_seeded(7, 16, 3);
+        let turning = PlayerInput {
+            steering: 1.0,
+            tucking: true,
+            braking: false,
+        };
+        for _ in 0..120 {
+            world.step(&turning, DT);
+        }
+
+        // The autopilot skis long enough for the world to scroll many times
+        let mut autopilot = Autopilot::new();
+        start(&mut world, &run);
+        for _ in 0..1200 {
+            let input = autopilot.input(&world);
+            run.inputs.push(input);
+            if world.step(&input, DT).1 {
+                break;
+            }
+        }
+        assert!(world.real_y > 60.0);
+
+        let replay = Replay::from_json(&run.to_json()).unwrap();
+        let mut replayed = new_world(
+            base_resources(),
+            &replay.mode,
+            replay.slope_width,
+            replay.height,
+        );
+        start(&mut replayed, &replay);
+        for tick in 0..replay.inputs.len() {
+            replayed.step(&replay.input(tick), DT);
+        }
+
+        assert_eq!(replayed.save_state(), world.save_state());
+    }
+
+    #[test]
+    fn packs_are_checked() {
+        let pack = |name: &str, version: &str| Pack {
+            name: name.to_owned(),
+            version: version.to_owned(),
+            author: "test".to_owned(),
+            directory: Path::new(name).to_owned(),
+        };
+        let packs = vec![pack("Skii", "0.1.0"), pack("Ice age", "1.0.0")];
+        let mut run = Replay::new(3, "classic", 7, 16, 0);
+        run.packs = pack_versions(&packs);
+
+        let replay = Replay::from_json(&run.to_json()).unwrap();
+        assert_eq!(replay.packs, run.packs);
+        assert!(replay.pack_mismatches(&packs).is_empty());
+
+        let updated = [pack("Skii", "0.1.0"), pack("Ice age", "1.1.0")];
+        assert_eq!(replay.pack_mismatches(&updated).len(), 1);
+        assert_eq!(replay.pack_mismatches(&packs[..1]).len(), 1);
+        let reordered = [pack("Ice age", "1.0.0"), pack("Skii", "0.1.0")];
+        assert_eq!(replay.pack_mismatches(&reordered).len(), 1);
+
+        // The replays recorded without the packs are played with any of them
+        let mut old = run.to_json();
+        old.as_object_mut().unwrap().remove("packs");
+        assert!(Replay::from_json(&old).unwrap().pack_mismatches(&packs).is_empty());
+    }
+}
@@ -0,0 +1,313 @@
+use serde_json::Value;
+
+use std::collections::VecDeque;
+use std::io;
+use game::{Object, Player, Row, TrailPoint, World};
+use types::Vector2;
+use loader::invalid;
+
+fn number(json: &Value, field: &str) -> io::Result<f32> {
+    json.as_f64()
+        .map(|number| number as f32)
+        .ok_or_else(|| invalid(format!("The saved game has no valid {}", field)))
+}
+
+fn vector(json: &Value, field: &str) -> io::Result<Vector2> {
+    Ok(Vector2::new(number(&json[0], field)?, number(&json[1], field)?))
+}
+
+/// A run read by `World::read_state`, with its tiles and objects already checked
+pub struct SavedState {
+    seed: u64,
+    rng_state: u64,
+    real_y: f32,
+    player: Player,
+    tiles: Vec<Vec<usize>>,
+    objects: Vec<(usize, Object)>,
+    trail: Vec<TrailPoint>,
+    map_rows: VecDeque<Row>,
+    generator: Value,
+}
+
+impl World {
+    /// Returns everything needed to continue the run later.
+    /// The tiles and objects are stored by the name of their type,
+    /// so that the saved game still loads when the types are sorted differently
+    pub fn save_state(&self) -> Value {
+        let tiles: Vec<Value> = self.tiles.iter().map(|row| self.row_json(row)).collect();
+        let map_rows: Vec<Value> = self.map_rows
+            .iter()
+            .map(|&(ref row, ref objects)| {
+                json!({
+                    "tiles": self.row_json(row),
+                    "objects": self.objects_json(objects),
+                })
+            })
+            .collect();
+        // Each point is stored as [x, y, rotation, intensity]
+        let trail: Vec<Value> = self.trail
+            .iter()
+            .map(|point| {
+                json!([
+                    point.position.x,
+                    point.position.y,
+                    point.rotation,
+                    point.intensity,
+                ])
+            })
+            .collect();
+
+        let player = &self.player;
+        let generator = self.generator
+            .as_ref()
+            .map_or(Value::Null, |generator| generator.state());
+        json!({
+            "seed": self.seed,
+            "rng": self.rng.state,
+            "real_y": self.real_y,
+            "player": {
+                "position": [player.position.x, player.position.y],
+                "rotation": player.rotation,
+                "velocity": [player.velocity.x, player.velocity.y],
+                "angular_velocity": player.angular_velocity,
+                "tucking": player.tucking,
+                "braking": player.braking,
+            },
+            "tiles": tiles,
+            "objects": self.objects_json(&self.objects),
+            "trail": trail,
+            "map_rows": map_rows,
+            "generator": generator,
+        })
+    }
+
+    /// Continues the run saved by `save_state`.
+    /// The world must have the generator of the saved run,
+    /// it is reset with the saved seed and then continues from where it was.
+    /// When the state is invalid the world is left as it is
+    pub fn load_state(&mut self, json: &Value) -> io::Result<()> {
+        let state = self.read_state(json)?;
+        self.restore_state(state);
+        Ok(())
+    }
+
+    /// Reads the run saved by `save_state` without changing the world,
+    /// so that it can be checked before switching to the generator of the run
+    pub fn read_state(&self, json: &Value) -> io::Result<SavedState> {
+        let seed = json["seed"]
+            .as_u64()
+            .ok_or_else(|| invalid("The saved game has no valid seed"))?;
+        let rng_state = json["rng"]
+            .as_u64()
+            .ok_or_else(|| invalid("The saved game has no valid rng"))?;
+        let real_y = number(&json["real_y"], "real_y")?;
+
+        let mut tiles = Vec::new();
+        let rows = json["tiles"]
+            .as_array()
+            .ok_or_else(|| invalid("The saved game has no valid tiles"))?;
+        for row in rows {
+            tiles.push(self.row_from_json(row)?);
+        }
+        let width = tiles.first().map_or(0, |row| row.len());
+        if width == 0 || tiles.iter().any(|row| row.len() != width) {
+            return Err(invalid("The saved game rows must be as wide"));
+        }
+        let objects = self.objects_from_json(&json["objects"])?;
+
+        // Missing in the games saved without a map
+        let mut map_rows = VecDeque::new();
+        if let Some(rows) = json["map_rows"].as_array() {
+            for row in rows {
+                map_rows.push_back((
+                    self.row_from_json(&row["tiles"])?,
+                    self.objects_from_json(&row["objects"])?,
+                ));
+            }
+        }
+
+        let mut trail = Vec::new();
+        let points = json["trail"]
+            .as_array()
+            .ok_or_else(|| invalid("The saved game has no valid trail"))?;
+        for point in points {
+            trail.push(TrailPoint {
+                position: vector(point, "trail")?,
+                rotation: number(&point[2], "trail")?,
+                intensity: number(&point[3], "trail")?,
+            });
+        }
+
+        let player_json = &json["player"];
+        let player = Player {
+            position: vector(&player_json["position"], "player")?,
+            rotation: number(&player_json["rotation"], "player")?,
+            velocity: vector(&player_json["velocity"], "player")?,
+            angular_velocity: number(&player_json["angular_velocity"], "player")?,
+            tucking: player_json["tucking"].as_bool().unwrap_or(false),
+            braking: player_json["braking"].as_bool().unwrap_or(false),
+        };
+
+        Ok(SavedState {
+            seed,
+            rng_state,
+            real_y,
+            player,
+            tiles,
+            objects,
+            trail,
+            map_rows,
+            generator: json["generator"].clone(),
+        })
+    }
+
+    /// Continues the run read by `read_state`
+    pub fn restore_state(&mut self, state: SavedState) {
+        // The generator sets itself up from the seed, as when the run started
+        let width = state.tiles[0].len() as u32;
+        let height = state.tiles.len() as u32;
+        self.reset_seeded(width, height, state.seed);
+        if let Some(ref mut generator) = self.generator {
+            generator.restore(&state.generator);
+        }
+
+        self.rng.state = state.rng_state;
+        self.real_y = state.real_y;
+        self.player = state.player;
+        self.tiles = state.tiles;
+        self.objects = state.objects;
+        self.trail = state.trail;
+        self.map_rows = state.map_rows;
+    }
+
+    /// Returns the names of the tiles in the row
+    fn row_json(&self, row: &[usize]) -> Value {
+        row.iter()
+            .map(|&id| Value::from(self.tile_types[id].name.clone()))
+            .collect()
+    }
+
+    fn row_from_json(&self, json: &Value) -> io::Result<Vec<usize>> {
+        let mut row = Vec::new();
+        let names = json.as_array()
+            .ok_or_else(|| invalid("The saved game has no valid tiles"))?;
+        for name in names {
+            let name = name.as_str()
+                .ok_or_else(|| invalid("The saved game has no valid tiles"))?;
+            let id = self.tile_id(name)
+                .ok_or_else(|| invalid(format!("The saved game uses the unknown tile {}", name)))?;
+            row.push(id);
+        }
+
+        Ok(row)
+    }
+
+    /// Returns the name, position and rotation of each object
+    fn objects_json(&self, objects: &[(usize, Object)]) -> Value {
+        objects
+            .iter()
+            .map(|&(id, ref object)| {
+                json!({
+                    "type": self.object_types[id].name,
+                    "position": [object.position.x, object.position.y],
+                    "rotation": object.rotation,
+                })
+            })
+            .collect()
+    }
+
+    fn objects_from_json(&self, json: &Value) -> io::Result<Vec<(usize, Object)>> {
+        let mut objects = Vec::new();
+        let objects_json = json.as_array()
+            .ok_or_else(|| invalid("The saved game has no valid objects"))?;
+        for object in objects_json {
+            let name = object["type"]
+                .as_str()
+                .ok_or_else(|| invalid("The saved game has no valid objects"))?;
+            let id = self.object_id(name)
+                .ok_or_else(|| invalid(format!("The saved game uses the unknown object {}", name)))?;
+            objects.push((
+                id,
+                Object {
+                    position: vector(&object["position"], "objects")?,
+                    rotation: number(&object["rotation"], "objects")?,
+                },
+            ));
+        }
+
+        Ok(objects)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use game::PlayerInput;
+    use loader::base_resources;
+    use map::Map;
+    use simulation::new_world;
+
+    fn world(mode: &str) -> World {
+        new_world(base_resources(), mode, 7, 16)
+    }
+
+    /// Skis down the slope turning a bit, so that the world scrolls and leaves a trail
+    fn ski(world: &mut World, updates: usize) {
+        for tick in 0..updates {
+            let input = PlayerInput {
+                steering: if tick % 40 < 20 { 0.3 } else { -0.3 },
+                tucking: true,
+                braking: false,
+            };
+            world.step(&input, 1.0 / 60.0);
+        }
+    }
+
+    /// Saves the world, loads it in a new one, and checks that both continue the same way
+    fn round_trip(mode: &str, mut saved: World) {
+        let state = saved.save_state();
+        let mut loaded = world(mode);
+        loaded.load_state(&state).unwrap();
+        assert_eq!(loaded.save_state(), state);
+
+        ski(&mut saved, 300);
+        ski(&mut loaded, 300);
+        assert_eq!(loaded.save_state(), saved.save_state());
+    }
+
+    #[test]
+    fn round_trip_keeps_the_generators() {
+        for mode in &["classic", "noise", "cellular"] {
+            let mut saved = world(mode);
+            saved.reset_seeded(7, 16, 5);
+            ski(&mut saved, 200);
+            assert!(!saved.trail.is_empty());
+            round_trip(mode, saved);
+        }
+    }
+
+    #[test]
+    fn round_trip_keeps_the_map_rows() {
+        let mut saved = world("classic");
+        let map = Map {
+            tiles: vec![vec!["snow".to_owned(); 7]; 40],
+            objects: vec![("tree1".to_owned(), Vector2::new(2.5, 30.5), 0.3)],
+        };
+        saved.load_map(&map).unwrap();
+        assert!(!saved.map_rows.is_empty());
+        round_trip("classic", saved);
+    }
+
+    #[test]
+    fn invalid_state_leaves_the_world() {
+        let mut loaded = world("classic");
+        loaded.reset_seeded(7, 16, 3);
+        let state = loaded.save_state();
+
+        let mut invalid = state.clone();
+        invalid["tiles"][0][0] = json!("lava");
+        assert!(loaded.load_state(&invalid).is_err());
+        assert!(loaded.load_state(&json!({})).is_err());
+        assert_eq!(loaded.save_state(), state);
+    }
+}
@@ -0,0 +1,255 @@
+use serde_json::Value;
+
+use std::collections::BTreeMap;
+use autopilot::Autopilot;
+use game::{Collision, PlayerInput, World};
+use map::Map;
+use generation::generator_from_name;
+use loader::Resources;
+
+// The names of the controllers, as given to `controller_from_name`
+pub const CONTROLLERS: [&str; 3] = ["straight", "zigzag", "autopilot"];
+// The updates the zigzag controller steers to one side before switching
+const ZIGZAG_TICKS: u32 = 45;
+// The width of each bar of the speed distribution, in meters per second
+const SPEED_BIN: f32 = 1.0;
+
+/// Something playing the game in place of the player
+pub trait Controller {
+    /// Returns the input of the player for the next update
+    fn input(&mut self, world: &World) -> PlayerInput;
+    /// Called when a new run starts
+    fn reset(&mut self) {}
+}
+
+/// Goes straight down the slope
+pub struct StraightController;
+
+impl Controller for StraightController {
+    fn input(&mut self, _world: &World) -> PlayerInput {
+        PlayerInput::none()
+    }
+}
+
+/// Steers to one side and then to the other, at a fixed pace
+pub struct ZigzagController {
+    tick: u32,
+}
+
+impl ZigzagController {
+    pub fn new() -> ZigzagController {
+        ZigzagController { tick: 0 }
+    }
+}
+
+impl Controller for ZigzagController {
+    fn input(&mut self, _world: &World) -> PlayerInput {
+        self.tick += 1;
+        // The first turn is half as long, so that the player stays around the center
+        let phase = (self.tick + ZIGZAG_TICKS / 2) / ZIGZAG_TICKS;
+        PlayerInput {
+            steering: if phase % 2 == 0 { 1.0 } else { -1.0 },
+            ..PlayerInput::none()
+        }
+    }
+
+    fn reset(&mut self) {
+        self.tick = 0;
+    }
+}
+
+pub fn controller_from_name(name: &str) -> Option<Box<Controller>> {
+    match name {
+        "straight" => Some(Box::new(StraightController)),
+        "zigzag" => Some(Box::new(ZigzagController::new())),
+        "autopilot" => Some(Box::new(Autopilot::new())),
+        _ => None,
+    }
+}
+
+// The modes of `new_world`, also listed in the mode select of the window
+pub const MODES: [&str; 4] = ["classic", "cellular", "noise", "flat"];
+
+/// Creates a world using the generator of the mode, `classic` keeps the one of the asset packs
+pub fn new_world(resources: Resources, mode: &str, slope_width: u32, height: u32) -> World {
+    let mut world = World::new(resources);
+
+    // The generator can only be replaced in a world with tiles
+    world.reset(slope_width, height);
+    if mode != "classic" {
+        world.set_generator(generator_from_name(mode).expect("Unknown mode"));
+    }
+
+    world
+}
+
+/// What happened during a simulated run
+pub struct RunStats {
+    pub seed: u64,
+    // The meters skied from the start of the run
+    pub distance: f32,
+    pub ticks: usize,
+    // The name of what the player crashed into, `edge` for the sides of the slope
+    pub crash: Option<String>,
+    // The speed of the player on each update, in meters per second
+    pub speeds: Vec<f32>,
+    // The objects generated during the run, and the rows they were generated in
+    pub objects: usize,
+    pub rows: usize,
+}
+
+impl RunStats {
+    pub fn average_speed(&self) -> f32 {
+        if self.speeds.is_empty() {
+            return 0.0;
+        }
+        self.speeds.iter().sum::<f32>() / self.speeds.len() as f32
+    }
+
+    pub fn max_speed(&self) -> f32 {
+        self.speeds.iter().cloned().fold(0.0, f32::max)
+    }
+
+    /// The objects generated every 100 meters of slope
+    pub fn object_density(&self) -> f32 {
+        if self.rows == 0 {
+            return 0.0;
+        }
+        self.objects as f32 / self.rows as f32 * 100.0
+    }
+}
+
+/// Plays a run of at most `ticks` updates lasting `dt` seconds each, controlled by `controller`
+pub fn simulate_run(
+    world: &mut World,
+    controller: &mut Controller,
+    map: Option<&Map>,
+    seed: u64,
+    start: u32,
+    ticks: usize,
+    dt: f32,
+) -> RunStats {
+    let width = world.width() as u32;
+    let height = world.height() as u32;
+    world.reset_seeded(width, height, seed);
+    if let Some(map) = map {
+        world
+            .load_map(map)
+            .expect("The map was checked before the runs");
+    }
+    world.advance(start);
+    controller.reset();
+
+    let mut stats = RunStats {
+        seed,
+        distance: 0.0,
+        ticks: 0,
+        crash: None,
+        speeds: Vec::with_capacity(ticks),
+        objects: world.objects.len(),
+        rows: world.height(),
+    };
+
+    for _ in 0..ticks {
+        let input = controller.input(world);
+        let (scrolled, crashed) = world.step(&input, dt);
+        stats.ticks += 1;
+        stats.speeds.push(world.player.velocity.norm());
+
+        // The new rows are on top of the world
+        if scrolled > 0 {
+            let first_row = (world.height() - scrolled as usize) as f32;
+            stats.rows += scrolled as usize;
+            stats.objects += world
+                .objects
+                .iter()
+                .filter(|&&(_, ref object)| object.position.y >= first_row)
+                .count();
+        }
+
+        if crashed {
+            stats.crash = world.collision().map(|collision| match collision {
+                Collision::Edge => "edge".to_owned(),
+                Collision::Object(id) => world.object_types[id].name.clone(),
+            });
+            break;
+        }
+    }
+
+    stats.distance = world.real_y + world.player.position.y - start as f32;
+    stats
+}
+
+/// Returns the runs as csv, one line for each run
+pub fn to_csv(runs: &[RunStats]) -> String {
+    let mut csv = "seed,distance,ticks,crash,average_speed,max_speed,objects_per_100m\n".to_owned();
+    for run in runs {
+        csv += &format!(
+            "{},{:.2},{},{},{:.3},{:.3},{:.2}\n",
+            run.seed,
+            run.distance,
+            run.ticks,
+            run.crash.as_ref().map_or("none", |crash| &crash[..]),
+            run.average_speed(),
+            run.max_speed(),
+            run.object_density()
+        );
+    }
+
+    csv
+}
+
+/// Returns the statistics of all the runs as json, followed by each run
+pub fn to_json(runs: &[RunStats]) -> Value {
+    let count = runs.len().max(1) as f32;
+    let average_distance = runs.iter().map(|run| run.distance).sum::<f32>() / count;
+
+    let mut crash_causes: BTreeMap<String, usize> = BTreeMap::new();
+    for run in runs {
+        let cause = run.crash.clone().unwrap_or_else(|| "none".to_owned());
+        *crash_causes.entry(cause).or_insert(0) += 1;
+    }
+
+    // How many updates were spent at each speed, each bar starts at its index times SPEED_BIN
+    let mut speed_distribution: Vec<usize> = Vec::new();
+    for speed in runs.iter().flat_map(|run| run.speeds.iter()) {
+        let bin = (speed / SPEED_BIN) as usize;
+        if bin >= speed_distribution.len() {
+            speed_distribution.resize(bin + 1, 0);
+        }
+        speed_distribution[bin] += 1;
+    }
+
+    let objects: usize = runs.iter().map(|run| run.objects).sum();
+    let rows: usize = runs.iter().map(|run| run.rows).sum();
+    let object_density = if rows > 0 {
+        objects as f32 / rows as f32 * 100.0
+    } else {
+        0.0
+    };
+
+    let runs_json: Vec<Value> = runs
+        .iter()
+        .map(|run| {
+            json!({
+                "seed": run.seed,
+                "distance": run.distance,
+                "ticks": run.ticks,
+                "crash": run.crash,
+                "average_speed": run.average_speed(),
+                "max_speed": run.max_speed(),
+                "objects_per_100m": run.object_density(),
+            })
+        })
+        .collect();
+
+    json!({
+        "runs": runs.len(),
+        "average_distance": average_distance,
+        "crash_causes": crash_causes,
+        "speed_bin": SPEED_BIN,
+        "speed_distribution": speed_distribution,
+        "objects_per_100m": object_density,
+        "each_run": runs_json,
+    })
+}
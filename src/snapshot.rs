@@ -0,0 +1,318 @@
+use std::io;
+use std::path::Path;
+
+use animation::player_state;
+use atlas::{Atlas, Sprite};
+use camera::Camera;
+use game::{World, TRAIL_COLOR};
+use image::{Rgba, RgbaImage};
+use types::{Point2, Vector2};
+
+/// Draws the world in software, with the textures of the atlas and the layout of the window:
+/// the tiles, the trail, the objects and the player, as seen by a camera resting on the player.
+/// It needs no window, so it works in headless runs, and the same world always gives the same image
+pub fn render(world: &World, atlas: &Atlas, size: (u32, u32), crashed: bool) -> RgbaImage {
+    let mut camera = Camera::new(size, 1.0);
+    camera.fit(size, world.width() as u32);
+    camera.snap(world);
+    let scale = camera.scale();
+
+    let mut canvas = RgbaImage::from_pixel(size.0, size.1, Rgba([255, 255, 255, 255]));
+
+    for y in 0..world.height() {
+        for x in 0..world.width() {
+            let texture = &world.tile_types[world.tiles[y][x]].texture;
+            let position = camera.world_to_screen(&Vector2::new(x as f32, y as f32));
+            draw_sprite(
+                &mut canvas,
+                atlas,
+                texture,
+                position,
+                scale,
+                0.0,
+                Point2::new(0.0, 0.0),
+            );
+        }
+    }
+
+    draw_trail(&mut canvas, world, &camera);
+
+    for &(object_id, ref object) in &world.objects {
+        let object_type = &world.object_types[object_id];
+        // The frame the window shows at the start, each column a bit out of step
+        let time = object.position.x * 0.3;
+        let texture = object_type
+            .animations
+            .frame_or("idle", time, object_type.texture);
+        draw_sprite(
+            &mut canvas,
+            atlas,
+            &texture,
+            camera.world_to_screen(&object.position),
+            scale,
+            object.rotation,
+            Point2::new(texture.width as f32 / 32.0, texture.height as f32 / 32.0),
+        );
+    }
+
+    let player_type = &world.player_type;
+    let texture = player_type.animations.frame_or(
+        player_state(&world.player, crashed),
+        0.0,
+        player_type.texture,
+    );
+    draw_sprite(
+        &mut canvas,
+        atlas,
+        &texture,
+        camera.world_to_screen(&world.player.position),
+        scale,
+        world.player.rotation,
+        Point2::new(texture.width as f32 / 32.0, texture.height as f32 / 32.0),
+    );
+
+    canvas
+}
+
+/// Draws the world and saves it as a png
+pub fn save(
+    world: &World,
+    atlas: &Atlas,
+    size: (u32, u32),
+    crashed: bool,
+    path: &Path,
+) -> io::Result<()> {
+    render(world, atlas, size, crashed).save(path)
+}
+
+/// Draws the sprite with its `offset` (a fraction of its size) on `dest`,
+/// scaled and rotated around it, like the window does
+fn draw_sprite(
+    canvas: &mut RgbaImage,
+    atlas: &Atlas,
+    sprite: &Sprite,
+    dest: Point2,
+    scale: f32,
+    rotation: f32,
+    offset: Point2,
+) {
+    let page = &atlas.pages[sprite.page];
+    let left = (sprite.region.x * page.width() as f32).round() as u32;
+    let top = (sprite.region.y * page.height() as f32).round() as u32;
+    let (width, height) = (sprite.width as f32, sprite.height as f32);
+    let pivot = Vector2::new(offset.x * width, offset.y * height);
+    let (sin, cos) = rotation.sin_cos();
+
+    // The rotated sprite is inside the circle around the pivot reaching its farthest corner
+    let corner = Vector2::new(pivot.x.max(width - pivot.x), pivot.y.max(height - pivot.y));
+    let reach = corner.norm() * scale;
+    let min_x = (dest.x - reach).floor().max(0.0) as u32;
+    let min_y = (dest.y - reach).floor().max(0.0) as u32;
+    let max_x = ((dest.x + reach).ceil().max(0.0) as u32).min(canvas.width());
+    let max_y = ((dest.y + reach).ceil().max(0.0) as u32).min(canvas.height());
+
+    for screen_y in min_y..max_y {
+        for screen_x in min_x..max_x {
+            // Takes the pixel center back to the sprite, undoing the rotation and scale
+            let dx = screen_x as f32 + 0.5 - dest.x;
+            let dy = screen_y as f32 + 0.5 - dest.y;
+            let x = (dx * cos + dy * sin) / scale + pivot.x;
+            let y = (dy * cos - dx * sin) / scale + pivot.y;
+            if x < 0.0 || y < 0.0 || x >= width || y >= height {
+                continue;
+            }
+
+            let texel = page.get_pixel(left + x as u32, top + y as u32);
+            blend(canvas, screen_x, screen_y, texel.data);
+        }
+    }
+}
+
+/// Draws the marks left by the skies, a line for each ski
+fn draw_trail(canvas: &mut RgbaImage, world: &World, camera: &Camera) {
+    let color = |intensity: f32| {
+        [
+            (TRAIL_COLOR[0] * 255.0) as u8,
+            (TRAIL_COLOR[1] * 255.0) as u8,
+            (TRAIL_COLOR[2] * 255.0) as u8,
+            (intensity.max(0.0).min(1.0) * 255.0) as u8,
+        ]
+    };
+
+    for points in world.trail.windows(2) {
+        let (from, to) = (&points[0], &points[1]);
+        // Points far apart are not part of the same movement
+        if (to.position - from.position).norm() > 1.0 {
+            continue;
+        }
+
+        let (from_left, from_right) = from.skies();
+        let (to_left, to_right) = to.skies();
+        for &(ref from_ski, ref to_ski) in &[(from_left, to_left), (from_right, to_right)] {
+            draw_line(
+                canvas,
+                camera.world_to_screen(from_ski),
+                camera.world_to_screen(to_ski),
+                camera.scale(),
+                color(to.intensity),
+            );
+        }
+    }
+}
+
+/// Draws a line `width` pixels wide
+fn draw_line(canvas: &mut RgbaImage, from: Point2, to: Point2, width: f32, color: [u8; 4]) {
+    let half_width = width / 2.0;
+    let min_x = (from.x.min(to.x) - half_width).floor().max(0.0) as u32;
+    let min_y = (from.y.min(to.y) - half_width).floor().max(0.0) as u32;
+    let max_x = ((from.x.max(to.x) + half_width).ceil().max(0.0) as u32).min(canvas.width());
+    let max_y = ((from.y.max(to.y) + half_width).ceil().max(0.0) as u32).min(canvas.height());
+
+    let segment = to - from;
+    let length_squared = segment.norm_squared().max(::std::f32::EPSILON);
+    for y in min_y..max_y {
+        for x in min_x..max_x {
+            let point = Point2::new(x as f32 + 0.5, y as f32 + 0.5);
+            // The closest point of the segment to the pixel center
+            let along = ((point - from).dot(&segment) / length_squared)
+                .max(0.0)
+                .min(1.0);
+            let closest = from + segment * along;
+            if (point - closest).norm() <= half_width {
+                blend(canvas, x, y, color);
+            }
+        }
+    }
+}
+
+/// Draws the color over the pixel, mixing them by its alpha
+fn blend(canvas: &mut RgbaImage, x: u32, y: u32, color: [u8; 4]) {
+    let alpha = color[3] as f32 / 255.0;
+    if alpha <= 0.0 {
+        return;
+    }
+
+    let pixel = canvas.get_pixel_mut(x, y);
+    for channel in 0..3 {
+        let below = pixel.data[channel] as f32;
+        pixel.data[channel] = (color[channel] as f32 * alpha + below * (1.0 - alpha)).round() as u8;
+    }
+    pixel.data[3] = 255;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use animation::Animations;
+    use atlas::AtlasBuilder;
+    use game::{Object, ObjectType, PlayerType, TileType};
+    use generation::FlatGenerator;
+    use loader::{base_pack, Resources};
+    use simulation::new_world;
+    use std::env;
+
+    // The picture the test world must give
+    const GOLDEN: &str = "tests/golden/snapshot.png";
+
+    /// A texture of one color, with another one in the rectangle
+    fn texture(base: [u8; 4], mark: [u8; 4], rect: (u32, u32, u32, u32)) -> RgbaImage {
+        let (left, top, right, bottom) = rect;
+        RgbaImage::from_fn(16, 16, |x, y| {
+            if x >= left && x < right && y >= top && y < bottom {
+                Rgba(mark)
+            } else {
+                Rgba(base)
+            }
+        })
+    }
+
+    /// A slope three tiles wide with two tile types and a tree
+    fn world() -> (World, Atlas) {
+        let mut builder = AtlasBuilder::new();
+        let clear = [0, 0, 0, 0];
+        builder.add("snow", texture([240, 240, 250, 255], [200, 200, 255, 255], (0, 0, 8, 8)));
+        builder.add("ice", texture([150, 200, 240, 255], [100, 150, 220, 255], (8, 8, 16, 16)));
+        builder.add("tree", texture(clear, [30, 120, 40, 255], (4, 2, 12, 14)));
+        builder.add("player", texture(clear, [200, 30, 30, 255], (6, 2, 10, 14)));
+        let atlas = builder.build();
+
+        let sprite = |name: &str| atlas.sprite(name).unwrap();
+        let tile = |name: &str, distribution: f32| TileType {
+            name: name.to_owned(),
+            texture: sprite(name),
+            forward_friction: 0.1,
+            sideway_friction: 1.0,
+            distribution,
+            noise_band: None,
+            trail_depth: 0.5,
+            particles: None,
+            glyph: None,
+        };
+        let resources = Resources {
+            player_type: PlayerType {
+                texture: sprite("player"),
+                animations: Animations::new(),
+            },
+            object_types: vec![ObjectType {
+                name: "tree".to_owned(),
+                texture: sprite("tree"),
+                animations: Animations::new(),
+                distribution: 0.1,
+                hitbox: Vector2::new(0.5, 0.5),
+                glyph: None,
+            }],
+            tile_types: vec![tile("snow", 1.0), tile("ice", 0.5)],
+            generator: Box::new(FlatGenerator),
+            segments: Vec::new(),
+        };
+
+        let mut world = World::new(resources);
+        world.reset_seeded(3, 10, 1);
+        for y in 0..world.height() {
+            for x in 0..world.width() {
+                world.tiles[y][x] = if (x + y) % 3 == 0 { 1 } else { 0 };
+            }
+        }
+        world.objects = vec![(0, Object::new(Vector2::new(0.5, 4.0)))];
+
+        (world, atlas)
+    }
+
+    /// Checks the image against the golden one, written again when SKII_UPDATE_GOLDEN is set
+    fn assert_golden(image: &RgbaImage, golden_path: &str) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(golden_path);
+        if env::var_os("SKII_UPDATE_GOLDEN").is_some() {
+            image.save(&path).unwrap();
+        }
+        let golden = ::image::open(&path)
+            .expect("Could not open the golden image")
+            .to_rgba();
+        assert_eq!(image.dimensions(), golden.dimensions());
+        assert!(
+            image.pixels().zip(golden.pixels()).all(|(a, b)| a == b),
+            "The snapshot differs from {}",
+            golden_path
+        );
+    }
+
+    #[test]
+    fn render_matches_golden() {
+        let (world, atlas) = world();
+        let image = render(&world, &atlas, (64, 144), false);
+        assert_golden(&image, GOLDEN);
+    }
+
+    /// The slopes generated by the base pack, so that a change to a generator shows up
+    #[test]
+    fn generated_slopes_match_golden() {
+        for &mode in &["cellular", "noise"] {
+            let (resources, atlas) = base_pack();
+            let mut world = new_world(resources, mode, 7, 16);
+            world.reset_seeded(7, 16, 7);
+            // The first rows are cleared for the start, the generated ones come after them
+            world.advance(16);
+            let image = render(&world, &atlas, (112, 256), false);
+            assert_golden(&image, &format!("tests/golden/{}.png", mode));
+        }
+    }
+}
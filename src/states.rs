@@ -0,0 +1,600 @@
+use ggez::{Context, GameResult};
+use ggez::event::{Keycode, MouseButton};
+use ggez::graphics;
+use ggez::graphics::{Color, DrawMode, Point2, Rect};
+use autopilot::Autopilot;
+use game::MIN_SLOPE_WIDTH;
+use editor::Editor;
+use input::{Action, ACTIONS};
+use renderer::ViewState;
+use simulation::MODES;
+
+// The seconds the autopilot behind the main menu stays crashed before starting again
+const DEMO_RESTART_DELAY: f32 = 2.0;
+
+/// What the view does with the states after an update or a key press
+pub enum Transition {
+    None,
+    // Shows a new state, returning to the current one when it is popped
+    Push(Box<State>),
+    // Returns to the previous state
+    Pop,
+    // Replaces the current state
+    Switch(Box<State>),
+    Quit,
+}
+
+/// A screen of the game, eg: the main menu or the slope being played.
+/// Only the state on top of the stack is updated and drawn
+pub trait State {
+    fn update(&mut self, _view: &mut ViewState, _dt: f32) -> Transition {
+        Transition::None
+    }
+
+    fn key_down(
+        &mut self,
+        view: &mut ViewState,
+        ctx: &mut Context,
+        keycode: Keycode,
+    ) -> Transition;
+
+    fn draw(&mut self, view: &mut ViewState, ctx: &mut Context) -> GameResult<()>;
+
+    // The mouse positions are in screen space
+    fn mouse_down(
+        &mut self,
+        _view: &mut ViewState,
+        _button: MouseButton,
+        _position: Point2,
+    ) -> Transition {
+        Transition::None
+    }
+
+    fn mouse_up(&mut self, _view: &mut ViewState, _button: MouseButton) -> Transition {
+        Transition::None
+    }
+
+    fn mouse_motion(&mut self, _view: &mut ViewState, _position: Point2) -> Transition {
+        Transition::None
+    }
+
+    // `amount` is positive when the wheel is scrolled away from the user
+    fn mouse_wheel(&mut self, _view: &mut ViewState, _amount: i32) -> Transition {
+        Transition::None
+    }
+}
+
+/// A vertical list of entries, moved through with Up and Down and chosen with Enter
+pub struct Menu {
+    pub selected: usize,
+}
+
+impl Menu {
+    pub fn new() -> Menu {
+        Menu { selected: 0 }
+    }
+
+    /// Moves the selection, returns the entry chosen with Enter
+    pub fn key_down(&mut self, keycode: Keycode, entries: usize) -> Option<usize> {
+        match keycode {
+            Keycode::Up => self.selected = (self.selected + entries - 1) % entries,
+            Keycode::Down => self.selected = (self.selected + 1) % entries,
+            Keycode::Return => return Some(self.selected),
+            _ => {}
+        }
+
+        None
+    }
+
+    /// Draws the entries one under the other, starting from `top`
+    pub fn draw(
+        &self,
+        view: &mut ViewState,
+        ctx: &mut Context,
+        entries: &[String],
+        top: f32,
+    ) -> GameResult<()> {
+        for (i, entry) in entries.iter().enumerate() {
+            let position = Point2::new(view.window_size.0 as f32 / 2.0, top + i as f32 * 30.0);
+            if i == self.selected {
+                let content = format!("> {} <", entry);
+                view.draw_text(ctx, &content, position, Color::new(0.2, 0.4, 0.8, 1.0))?;
+            } else {
+                view.draw_text(ctx, entry, position, graphics::BLACK)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Covers the slope with a faded white, so that the menus stand out
+fn draw_overlay(view: &mut ViewState, ctx: &mut Context) -> GameResult<()> {
+    let (width, height) = view.window_size;
+    graphics::set_color(ctx, Color::new(1.0, 1.0, 1.0, 0.7))?;
+    graphics::rectangle(
+        ctx,
+        DrawMode::Fill,
+        Rect::new(0.0, 0.0, width as f32, height as f32),
+    )?;
+    view.draw_calls += 1;
+
+    // Images use the current color too
+    graphics::set_color(ctx, graphics::WHITE)
+}
+
+/// Draws a title centered at the given height
+fn draw_title(view: &mut ViewState, ctx: &mut Context, title: &str, y: f32) -> GameResult<()> {
+    let position = Point2::new(view.window_size.0 as f32 / 2.0, y);
+    view.draw_text(ctx, title, position, graphics::BLACK)
+}
+
+/// The first screen, with the autopilot skiing behind it
+pub struct MainMenu {
+    menu: Menu,
+    // The seconds since the autopilot crashed
+    crashed_time: f32,
+}
+
+impl MainMenu {
+    pub fn new() -> MainMenu {
+        MainMenu {
+            menu: Menu::new(),
+            crashed_time: 0.0,
+        }
+    }
+}
+
+impl State for MainMenu {
+    fn update(&mut self, view: &mut ViewState, dt: f32) -> Transition {
+        if view.autopilot.is_none() {
+            view.autopilot = Some(Box::new(Autopilot::new()));
+            view.restart();
+        }
+
+        view.update_world(dt);
+        if view.dead {
+            self.crashed_time += dt;
+            if self.crashed_time > DEMO_RESTART_DELAY {
+                self.crashed_time = 0.0;
+                view.restart();
+            }
+        }
+
+        Transition::None
+    }
+
+    fn key_down(
+        &mut self,
+        view: &mut ViewState,
+        ctx: &mut Context,
+        keycode: Keycode,
+    ) -> Transition {
+        // "Continue" is on top of the other entries when there is a saved run
+        let continues = view.has_saved_game as usize;
+        let choice = match self.menu.key_down(keycode, 6 + continues) {
+            Some(0) if view.has_saved_game => {
+                return match view.load_game(ctx) {
+                    Ok(()) => Transition::Switch(Box::new(Paused::new())),
+                    Err(error) => {
+                        eprintln!("Could not continue the saved game: {}", error);
+                        self.menu.selected = 0;
+                        Transition::None
+                    }
+                };
+            }
+            choice => choice.map(|i| i - continues),
+        };
+
+        match choice {
+            Some(0) => {
+                view.autopilot = None;
+                view.restart();
+                Transition::Switch(Box::new(Playing))
+            }
+            // The autopilot keeps skiing, without the menu
+            Some(1) => {
+                view.restart();
+                Transition::Switch(Box::new(Playing))
+            }
+            Some(2) => Transition::Push(Box::new(ModeSelect::new(view))),
+            Some(3) => Transition::Switch(Box::new(Editor::new(view))),
+            Some(4) => Transition::Push(Box::new(Settings::new())),
+            Some(5) => Transition::Quit,
+            _ => Transition::None,
+        }
+    }
+
+    fn draw(&mut self, view: &mut ViewState, ctx: &mut Context) -> GameResult<()> {
+        view.draw_world(ctx)?;
+        draw_overlay(view, ctx)?;
+
+        let top = view.window_size.1 as f32 / 3.0;
+        draw_title(view, ctx, "Skii", top)?;
+        let mut entries = Vec::new();
+        if view.has_saved_game {
+            entries.push("Continue".to_owned());
+        }
+        entries.extend(vec![
+            "Play".to_owned(),
+            "Watch the AI".to_owned(),
+            format!("Mode: {}", view.mode),
+            "Editor".to_owned(),
+            "Settings".to_owned(),
+            "Quit".to_owned(),
+        ]);
+        self.menu.draw(view, ctx, &entries, top + 60.0)
+    }
+}
+
+/// Chooses the generator of the slope
+pub struct ModeSelect {
+    menu: Menu,
+}
+
+impl ModeSelect {
+    pub fn new(view: &ViewState) -> ModeSelect {
+        let mut menu = Menu::new();
+        menu.selected = MODES
+            .iter()
+            .position(|&mode| mode == view.mode)
+            .unwrap_or(0);
+
+        ModeSelect { menu }
+    }
+}
+
+impl State for ModeSelect {
+    fn key_down(
+        &mut self,
+        view: &mut ViewState,
+        _ctx: &mut Context,
+        keycode: Keycode,
+    ) -> Transition {
+        if keycode == Keycode::Escape {
+            return Transition::Pop;
+        }
+
+        match self.menu.key_down(keycode, MODES.len() + 1) {
+            Some(i) if i < MODES.len() => {
+                view.select_mode(MODES[i]);
+                Transition::Pop
+            }
+            Some(_) => Transition::Pop,
+            None => Transition::None,
+        }
+    }
+
+    fn draw(&mut self, view: &mut ViewState, ctx: &mut Context) -> GameResult<()> {
+        view.draw_world(ctx)?;
+        draw_overlay(view, ctx)?;
+
+        let top = view.window_size.1 as f32 / 3.0;
+        draw_title(view, ctx, "Mode", top)?;
+        let mut entries: Vec<String> = MODES.iter().map(|&mode| mode.to_owned()).collect();
+        entries.push("Back".to_owned());
+        self.menu.draw(view, ctx, &entries, top + 60.0)
+    }
+}
+
+/// The slope being skied
+pub struct Playing;
+
+impl State for Playing {
+    fn update(&mut self, view: &mut ViewState, dt: f32) -> Transition {
+        view.update_world(dt);
+
+        if view.dead {
+            Transition::Switch(Box::new(GameOver::new()))
+        } else {
+            Transition::None
+        }
+    }
+
+    fn key_down(
+        &mut self,
+        view: &mut ViewState,
+        _ctx: &mut Context,
+        keycode: Keycode,
+    ) -> Transition {
+        match view.input.action(keycode) {
+            Some(Action::Pause) => Transition::Switch(Box::new(Paused::new())),
+            Some(Action::Restart) => {
+                view.restart();
+                Transition::None
+            }
+            _ => Transition::None,
+        }
+    }
+
+    fn draw(&mut self, view: &mut ViewState, ctx: &mut Context) -> GameResult<()> {
+        view.draw_world(ctx)?;
+        view.draw_alive_ui(ctx)
+    }
+}
+
+/// The slope stopped in the middle of a run
+pub struct Paused {
+    menu: Menu,
+}
+
+impl Paused {
+    pub fn new() -> Paused {
+        Paused { menu: Menu::new() }
+    }
+}
+
+impl State for Paused {
+    fn key_down(
+        &mut self,
+        view: &mut ViewState,
+        ctx: &mut Context,
+        keycode: Keycode,
+    ) -> Transition {
+        if keycode == Keycode::Escape || view.input.action(keycode) == Some(Action::Pause) {
+            return Transition::Switch(Box::new(Playing));
+        }
+
+        // The runs of the autopilot are only watched, so they can not be saved
+        let saves = view.autopilot.is_none() as usize;
+        match self.menu.key_down(keycode, 4 + saves) {
+            Some(0) => Transition::Switch(Box::new(Playing)),
+            Some(1) => {
+                view.restart();
+                Transition::Switch(Box::new(Playing))
+            }
+            Some(2) => Transition::Push(Box::new(Settings::new())),
+            Some(3) => {
+                view.restart();
+                Transition::Switch(Box::new(MainMenu::new()))
+            }
+            Some(4) if view.autopilot.is_none() => match view.save_game(ctx) {
+                Ok(()) => Transition::Quit,
+                Err(error) => {
+                    eprintln!("Could not save the game: {}", error);
+                    Transition::None
+                }
+            },
+            _ => Transition::None,
+        }
+    }
+
+    fn draw(&mut self, view: &mut ViewState, ctx: &mut Context) -> GameResult<()> {
+        view.draw_world(ctx)?;
+        view.draw_alive_ui(ctx)?;
+        draw_overlay(view, ctx)?;
+
+        let top = view.window_size.1 as f32 / 3.0;
+        draw_title(view, ctx, "Paused", top)?;
+        let mut entries = vec![
+            "Resume".to_owned(),
+            "Restart".to_owned(),
+            "Settings".to_owned(),
+            "Main menu".to_owned(),
+        ];
+        if view.autopilot.is_none() {
+            entries.push("Save and quit".to_owned());
+        }
+        self.menu.draw(view, ctx, &entries, top + 60.0)
+    }
+}
+
+/// The player crashed, the run is over
+pub struct GameOver {
+    menu: Menu,
+}
+
+impl GameOver {
+    pub fn new() -> GameOver {
+        GameOver { menu: Menu::new() }
+    }
+}
+
+impl State for GameOver {
+    fn update(&mut self, view: &mut ViewState, dt: f32) -> Transition {
+        // The snow keeps falling and the screen keeps shaking
+        view.update_world(dt);
+        Transition::None
+    }
+
+    fn key_down(
+        &mut self,
+        view: &mut ViewState,
+        _ctx: &mut Context,
+        keycode: Keycode,
+    ) -> Transition {
+        let choice = if keycode == Keycode::Escape {
+            Some(1)
+        } else if view.input.action(keycode) == Some(Action::Restart) {
+            Some(0)
+        } else {
+            self.menu.key_down(keycode, 2)
+        };
+
+        match choice {
+            Some(0) => {
+                view.restart();
+                Transition::Switch(Box::new(Playing))
+            }
+            Some(_) => {
+                view.restart();
+                Transition::Switch(Box::new(MainMenu::new()))
+            }
+            None => Transition::None,
+        }
+    }
+
+    fn draw(&mut self, view: &mut ViewState, ctx: &mut Context) -> GameResult<()> {
+        view.draw_world(ctx)?;
+        view.draw_dead_ui(ctx)?;
+
+        let top = view.window_size.1 as f32 / 2.0 + 70.0;
+        let entries = ["Restart".to_owned(), "Main menu".to_owned()];
+        self.menu.draw(view, ctx, &entries, top)
+    }
+}
+
+/// Changes how the game is played and drawn
+pub struct Settings {
+    menu: Menu,
+}
+
+impl Settings {
+    pub fn new() -> Settings {
+        Settings { menu: Menu::new() }
+    }
+}
+
+impl State for Settings {
+    fn key_down(
+        &mut self,
+        view: &mut ViewState,
+        _ctx: &mut Context,
+        keycode: Keycode,
+    ) -> Transition {
+        if keycode == Keycode::Escape {
+            return Transition::Pop;
+        }
+
+        // Left and Right change the selected value
+        let change = match keycode {
+            Keycode::Left => -1,
+            Keycode::Right => 1,
+            _ => 0,
+        };
+        match self.menu.selected {
+            0 => {
+                let width = view.slope_width as i32 + change;
+                view.slope_width = width.max(MIN_SLOPE_WIDTH as i32) as u32;
+            }
+            1 if change != 0 => view.batched = !view.batched,
+            _ => {}
+        }
+
+        match self.menu.key_down(keycode, 4) {
+            Some(1) => {
+                view.batched = !view.batched;
+                Transition::None
+            }
+            Some(2) => Transition::Push(Box::new(Controls::new())),
+            Some(3) => Transition::Pop,
+            _ => Transition::None,
+        }
+    }
+
+    fn draw(&mut self, view: &mut ViewState, ctx: &mut Context) -> GameResult<()> {
+        view.draw_world(ctx)?;
+        draw_overlay(view, ctx)?;
+
+        let top = view.window_size.1 as f32 / 3.0;
+        draw_title(view, ctx, "Settings", top)?;
+        let entries = [
+            format!("Slope width: {}", view.slope_width),
+            format!(
+                "Drawing: {}",
+                if view.batched { "batched" } else { "sprites" }
+            ),
+            "Controls".to_owned(),
+            "Back".to_owned(),
+        ];
+        self.menu.draw(view, ctx, &entries, top + 60.0)?;
+
+        let position = Point2::new(view.window_size.0 as f32 / 2.0, top + 210.0);
+        view.draw_text(
+            ctx,
+            "Left and Right change a value, the slope width is used from the next run",
+            position,
+            graphics::BLACK,
+        )
+    }
+}
+
+/// Binds the keys to the actions and sets up the gamepad stick,
+/// the settings are saved when leaving
+pub struct Controls {
+    menu: Menu,
+    // Set after choosing an action, the next key pressed is bound to it
+    waiting: bool,
+}
+
+impl Controls {
+    pub fn new() -> Controls {
+        Controls {
+            menu: Menu::new(),
+            waiting: false,
+        }
+    }
+}
+
+impl State for Controls {
+    fn key_down(
+        &mut self,
+        view: &mut ViewState,
+        ctx: &mut Context,
+        keycode: Keycode,
+    ) -> Transition {
+        if self.waiting {
+            // Escape cancels, keeping the key already bound
+            if keycode != Keycode::Escape {
+                view.input.bind(ACTIONS[self.menu.selected], keycode);
+            }
+            self.waiting = false;
+            return Transition::None;
+        }
+
+        // Left and Right change the stick settings
+        let change = match keycode {
+            Keycode::Left => -1.0,
+            Keycode::Right => 1.0,
+            _ => 0.0,
+        };
+        let deadzone = ACTIONS.len();
+        let sensitivity = ACTIONS.len() + 1;
+        if self.menu.selected == deadzone {
+            view.input.deadzone = (view.input.deadzone + change * 0.05).max(0.0).min(0.9);
+        } else if self.menu.selected == sensitivity {
+            view.input.sensitivity = (view.input.sensitivity + change * 0.1).max(0.1);
+        }
+
+        let leave = match self.menu.key_down(keycode, ACTIONS.len() + 3) {
+            Some(i) if i < ACTIONS.len() => {
+                self.waiting = true;
+                false
+            }
+            Some(i) => i == ACTIONS.len() + 2,
+            None => keycode == Keycode::Escape,
+        };
+
+        if leave {
+            if let Err(error) = view.input.save(ctx) {
+                eprintln!("Could not save the settings: {}", error);
+            }
+            Transition::Pop
+        } else {
+            Transition::None
+        }
+    }
+
+    fn draw(&mut self, view: &mut ViewState, ctx: &mut Context) -> GameResult<()> {
+        view.draw_world(ctx)?;
+        draw_overlay(view, ctx)?;
+
+        let top = view.window_size.1 as f32 / 4.0;
+        draw_title(view, ctx, "Controls", top)?;
+        let mut entries: Vec<String> = ACTIONS
+            .iter()
+            .enumerate()
+            .map(|(i, action)| {
+                if self.waiting && i == self.menu.selected {
+                    format!("{}: press a key, Escape to cancel", action.label())
+                } else {
+                    format!("{}: {}", action.label(), view.input.key(*action).name())
+                }
+            })
+            .collect();
+        entries.push(format!("Stick deadzone: {:.2}", view.input.deadzone));
+        entries.push(format!("Stick sensitivity: {:.1}", view.input.sensitivity));
+        entries.push("Back".to_owned());
+        self.menu.draw(view, ctx, &entries, top + 60.0)
+    }
+}
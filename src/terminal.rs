@@ -0,0 +1,228 @@
+use game::{Glyph, World};
+use crossterm::tty::IsTty;
+use types::Color;
+
+pub use self::play::play;
+
+// Each tile is drawn two characters wide, so that it looks about square
+const CELL_WIDTH: usize = 2;
+
+/// Draws the world as lines of characters, laid out as in the window.
+/// With `colored` the characters are colored with ANSI escape codes
+pub fn render(world: &World, crashed: bool, colored: bool) -> Vec<String> {
+    let default_tile = Glyph {
+        character: '.',
+        color: Color::new(1.0, 1.0, 1.0, 1.0),
+    };
+    let default_object = Glyph {
+        character: '*',
+        color: Color::new(0.2, 0.7, 0.2, 1.0),
+    };
+    let player = Glyph {
+        character: if crashed { 'X' } else { '@' },
+        color: Color::new(0.9, 0.1, 0.1, 1.0),
+    };
+
+    let mut grid: Vec<Vec<Glyph>> = world
+        .tiles
+        .iter()
+        .map(|row| {
+            row.iter()
+                .flat_map(|&tile| {
+                    let glyph = world.tile_types[tile].glyph.unwrap_or(default_tile);
+                    vec![glyph; CELL_WIDTH]
+                })
+                .collect()
+        })
+        .collect();
+
+    let objects = world.objects.iter().map(|&(id, ref object)| {
+        let glyph = world.object_types[id].glyph.unwrap_or(default_object);
+        (glyph, object.position.x, object.position.y)
+    });
+    let positions = objects.chain(Some((player, world.player.position.x, world.player.position.y)));
+    for (glyph, x, y) in positions {
+        if x < 0.0 || y < 0.0 {
+            continue;
+        }
+        let column = (x * CELL_WIDTH as f32) as usize;
+        if let Some(cell) = grid
+            .get_mut(y as usize)
+            .and_then(|row| row.get_mut(column))
+        {
+            *cell = glyph;
+        }
+    }
+
+    // Larger rows are higher on the screen
+    grid.iter()
+        .rev()
+        .map(|row| {
+            let mut line = String::new();
+            let mut last_color = None;
+            for glyph in row {
+                if colored && last_color != Some(glyph.color) {
+                    line += &ansi_color(glyph.color);
+                    last_color = Some(glyph.color);
+                }
+                line.push(glyph.character);
+            }
+            if colored {
+                line += "\x1b[0m";
+            }
+            line
+        })
+        .collect()
+}
+
+/// Whether the standard output is a terminal, where the colors can be shown
+pub fn is_terminal() -> bool {
+    ::std::io::stdout().is_tty()
+}
+
+/// The escape code setting the color of the following characters
+fn ansi_color(color: Color) -> String {
+    let channel = |value: f32| (value.max(0.0).min(1.0) * 255.0) as u8;
+    format!(
+        "\x1b[38;2;{};{};{}m",
+        channel(color.r),
+        channel(color.g),
+        channel(color.b)
+    )
+}
+
+mod play {
+    use std::io;
+    use std::io::prelude::*;
+    use std::time::{Duration, Instant};
+    use crossterm::{ExecutableCommand, QueueableCommand};
+    use crossterm::cursor::{Hide, MoveTo, Show};
+    use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+    use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+    use game::{PlayerInput, World};
+    use map::Map;
+    use random::random_seed;
+    use super::render;
+
+    // The updates a key keeps acting after being pressed,
+    // most terminals do not tell when a key is released so holding it repeats the presses
+    const KEY_HOLD_TICKS: u32 = 10;
+
+    /// Plays the game in the terminal: the arrows steer, tuck and brake, R restarts and Q quits.
+    /// Every run uses the seed, a random one when missing, and starts on the map if given
+    pub fn play(
+        world: &mut World,
+        map: Option<&Map>,
+        seed: Option<u64>,
+        start: u32,
+        dt: f32,
+    ) -> io::Result<()> {
+        let mut screen = io::stdout();
+        terminal::enable_raw_mode()?;
+        screen.execute(EnterAlternateScreen)?.execute(Hide)?;
+
+        // The terminal is restored even when the game stops on an error
+        let result = run(&mut screen, world, map, seed, start, dt);
+        screen.execute(Show)?.execute(LeaveAlternateScreen)?;
+        terminal::disable_raw_mode()?;
+        result
+    }
+
+    fn run<W: Write>(
+        screen: &mut W,
+        world: &mut World,
+        map: Option<&Map>,
+        seed: Option<u64>,
+        start: u32,
+        dt: f32,
+    ) -> io::Result<()> {
+        let (width, height) = (world.width() as u32, world.height() as u32);
+        let restart = |world: &mut World| {
+            world.reset_seeded(width, height, seed.unwrap_or_else(random_seed));
+            if let Some(map) = map {
+                world
+                    .load_map(map)
+                    .expect("The map was checked before playing");
+            }
+            world.advance(start);
+        };
+        restart(world);
+
+        // The updates left for each key: steer left, steer right, tuck and brake
+        let mut held = [0; 4];
+        let mut crashed = false;
+        // The game is updated with fixed steps, whatever the time taken by the drawing
+        let step = Duration::new(0, (dt * 1_000_000_000.0) as u32);
+        let mut next_step = Instant::now();
+        'game: loop {
+            // Handles the keys pressed until the next update is due
+            loop {
+                let now = Instant::now();
+                if now >= next_step || !event::poll(next_step - now)? {
+                    break;
+                }
+                let key = match event::read()? {
+                    Event::Key(key) if key.kind != KeyEventKind::Release => key,
+                    _ => continue,
+                };
+                match key.code {
+                    KeyCode::Left => held[0] = KEY_HOLD_TICKS,
+                    KeyCode::Right => held[1] = KEY_HOLD_TICKS,
+                    KeyCode::Down => held[2] = KEY_HOLD_TICKS,
+                    KeyCode::Up => held[3] = KEY_HOLD_TICKS,
+                    KeyCode::Char('r') => {
+                        restart(world);
+                        crashed = false;
+                    }
+                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        break 'game
+                    }
+                    KeyCode::Char('q') | KeyCode::Esc => break 'game,
+                    _ => {}
+                }
+            }
+
+            // Catches up with every update due, when the drawing took longer than a step
+            while next_step <= Instant::now() {
+                next_step += step;
+                if crashed {
+                    continue;
+                }
+
+                let mut steering = 0.0;
+                if held[0] > 0 {
+                    steering -= 1.0;
+                }
+                if held[1] > 0 {
+                    steering += 1.0;
+                }
+                let input = PlayerInput {
+                    steering,
+                    tucking: held[2] > 0,
+                    braking: held[3] > 0,
+                };
+                crashed = world.step(&input, dt).1;
+
+                for ticks in &mut held {
+                    *ticks = ticks.saturating_sub(1);
+                }
+            }
+
+            screen.queue(MoveTo(0, 0))?;
+            for line in render(world, crashed, true) {
+                write!(screen, "{}\r\n", line)?;
+            }
+            let distance = world.real_y + world.player.position.y;
+            if crashed {
+                write!(screen, "Crashed after {:.0} meters, R restarts, Q quits", distance)?;
+            } else {
+                write!(screen, "{:.0} meters, the arrows steer, tuck and brake", distance)?;
+            }
+            screen.queue(Clear(ClearType::UntilNewLine))?;
+            write!(screen, "\r\n")?;
+            screen.flush()?;
+        }
+
+        Ok(())
+    }
+}
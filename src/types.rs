@@ -0,0 +1,42 @@
+use nalgebra;
+
+pub type Point2 = nalgebra::Point2<f32>;
+pub type Vector2 = nalgebra::Vector2<f32>;
+
+// With the window the colors and rectangles are the ones of ggez, so they can be drawn as they are
+#[cfg(feature = "window")]
+pub use ggez::graphics::{Color, Rect};
+
+/// A color with its channels in the 0.0..1.0 range
+#[cfg(not(feature = "window"))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+#[cfg(not(feature = "window"))]
+impl Color {
+    pub fn new(r: f32, g: f32, b: f32, a: f32) -> Color {
+        Color { r, g, b, a }
+    }
+}
+
+/// A rectangle from its top left corner
+#[cfg(not(feature = "window"))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+}
+
+#[cfg(not(feature = "window"))]
+impl Rect {
+    pub fn new(x: f32, y: f32, w: f32, h: f32) -> Rect {
+        Rect { x, y, w, h }
+    }
+}
@@ -0,0 +1,258 @@
+use serde_json::Value;
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use loader::{config_file, read_config, try_load_resources, Pack, CONFIG_DIR, MANIFEST_FILE};
+
+/// The problems found in an asset pack, errors stop the game from loading it
+pub struct Report {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl Report {
+    fn new() -> Report {
+        Report {
+            errors: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    fn error(&mut self, file: &str, message: String) {
+        self.errors.push(format!("{}: {}", file, message));
+    }
+
+    fn warning(&mut self, file: &str, message: String) {
+        self.warnings.push(format!("{}: {}", file, message));
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for error in &self.errors {
+            writeln!(f, "error: {}", error)?;
+        }
+        for warning in &self.warnings {
+            writeln!(f, "warning: {}", warning)?;
+        }
+        write!(
+            f,
+            "{} errors, {} warnings",
+            self.errors.len(),
+            self.warnings.len()
+        )
+    }
+}
+
+/// Loads the asset pack in the directory as the game would, without a window,
+/// then looks for the problems that do not stop it from loading.
+/// The pack is layered over the packs in `base`, which provide the textures and types it does not have
+pub fn validate_pack(directory: &Path, base: &[PathBuf]) -> Report {
+    let mut report = Report::new();
+
+    let mut packs = Vec::new();
+    for layer in base {
+        match Pack::load(layer) {
+            Ok(pack) => packs.push(pack),
+            Err(error) => report.error(
+                &layer.join(MANIFEST_FILE).display().to_string(),
+                error.to_string(),
+            ),
+        }
+    }
+    // The description files are still checked without a valid manifest
+    let pack = Pack::load(directory).unwrap_or_else(|error| {
+        report.error(MANIFEST_FILE, error.to_string());
+        Pack {
+            name: String::new(),
+            version: String::new(),
+            author: String::new(),
+            directory: directory.to_owned(),
+        }
+    });
+    packs.push(pack);
+
+    if let Err(errors) = try_load_resources(&packs) {
+        report.errors.extend(errors);
+    }
+
+    // The warnings are only about the files of the pack, but take into account the ones below it
+    let files = read_config(&packs, &mut Vec::new());
+    let checked = read_config(&packs[packs.len() - 1..], &mut Vec::new());
+    for &(ref name, ref json) in &checked {
+        if json["type"] == "tile" || json["type"] == "object" {
+            check_distribution(&mut report, name, &json["properties"]);
+        }
+    }
+    check_players(&mut report, &files);
+    check_reachable(&mut report, &files, &checked);
+
+    report
+}
+
+fn check_distribution(report: &mut Report, name: &str, json: &Value) {
+    if let Some(distribution) = json["distribution"].as_f64() {
+        if distribution <= 0.0 {
+            report.warning(
+                &config_file(name),
+                format!("the distribution is {}, it is never generated", distribution),
+            );
+        } else if distribution > 1.0 {
+            report.warning(
+                &config_file(name),
+                format!(
+                    "the distribution is {}, more than the highest chance of 1.0",
+                    distribution
+                ),
+            );
+        }
+    }
+}
+
+fn check_players(report: &mut Report, files: &[(String, Value)]) {
+    let players = files.iter().filter(|&&(_, ref json)| json["type"] == "player").count();
+    if players > 1 {
+        report.warning(
+            CONFIG_DIR,
+            format!(
+                "{} player files were found, only the last one is used",
+                players
+            ),
+        );
+    }
+}
+
+/// Warns about the tiles of the pack that the generator never produces
+fn check_reachable(report: &mut Report, files: &[(String, Value)], checked: &[(String, Value)]) {
+    let tiles: Vec<&str> = files
+        .iter()
+        .filter(|&&(_, ref json)| json["type"] == "tile")
+        .map(|&(ref name, _)| &name[..])
+        .collect();
+    let used_tiles: Vec<&str> = files
+        .iter()
+        .filter_map(|&(_, ref json)| json["properties"]["legend"].as_object())
+        .flat_map(|legend| legend.values())
+        .filter_map(|name| name.as_str())
+        .collect();
+    let has_noise = files.iter().any(|&(_, ref json)| {
+        json["type"] == "generator" && json["properties"]["name"] == "noise"
+    });
+
+    // The first tile is the most common one, produced wherever the generator has no other choice
+    for &(ref name, ref json) in checked {
+        if json["type"] != "tile" {
+            continue;
+        }
+        let properties = &json["properties"];
+        let reachable = tiles.first() == Some(&&name[..])
+            || used_tiles.contains(&&name[..])
+            || properties["distribution"]
+                .as_f64()
+                .map_or(false, |chance| chance > 0.0)
+            || (has_noise && properties["noise_band"].is_object());
+        if !reachable {
+            report.warning(
+                &config_file(name),
+                "the tile can never be generated".to_owned(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use loader::{base_dir, temp_pack};
+    use std::fs;
+
+    fn tile(texture: &str, distribution: f64) -> Value {
+        json!({
+            "type": "tile",
+            "properties": {
+                "texture": texture,
+                "forward_friction": 0.1,
+                "sideway_friction": 5.0,
+                "distribution": distribution,
+            },
+        })
+    }
+
+    /// Validates the pack over the base one, then removes it
+    fn validate(name: &str, config: &[(&str, Value)], textures: &[&str]) -> Report {
+        let directory = temp_pack(name, config, textures);
+        let report = validate_pack(&directory, &[base_dir()]);
+        fs::remove_dir_all(directory).unwrap();
+        report
+    }
+
+    fn mentions(messages: &[String], file: &str, text: &str) -> bool {
+        messages
+            .iter()
+            .any(|message| message.starts_with(file) && message.contains(text))
+    }
+
+    #[test]
+    fn valid_pack() {
+        let report = validate("valid", &[("ice", tile("ice.png", 0.5))], &["ice.png"]);
+        assert!(report.is_valid(), "{}", report);
+        assert!(report.warnings.is_empty(), "{}", report);
+    }
+
+    #[test]
+    fn missing_textures() {
+        let report = validate("missing-textures", &[("ice", tile("ice.png", 0.5))], &[]);
+        assert!(!report.is_valid());
+        assert!(mentions(&report.errors, "config/ice.json", "ice.png"), "{}", report);
+
+        // The textures of the base pack are inherited
+        let report = validate("base-textures", &[("ice", tile("snow.png", 0.5))], &[]);
+        assert!(report.is_valid(), "{}", report);
+    }
+
+    #[test]
+    fn missing_player() {
+        let directory = temp_pack("missing-player", &[("ice", tile("ice.png", 0.5))], &["ice.png"]);
+        let report = validate_pack(&directory, &[]);
+        fs::remove_dir_all(directory).unwrap();
+        assert!(!report.is_valid());
+        assert!(mentions(&report.errors, "config", "no player file"), "{}", report);
+    }
+
+    #[test]
+    fn distribution_warnings() {
+        let config = [
+            ("never", tile("snow.png", 0.0)),
+            ("always", tile("snow.png", 2.0)),
+        ];
+        let report = validate("distributions", &config, &[]);
+        assert!(report.is_valid(), "{}", report);
+        assert!(mentions(&report.warnings, "config/never.json", "never generated"), "{}", report);
+        assert!(mentions(&report.warnings, "config/always.json", "more than"), "{}", report);
+    }
+
+    #[test]
+    fn unreachable_tiles() {
+        let segment = json!({
+            "type": "segment",
+            "properties": {
+                "rate": 0.1,
+                "legend": { "p": "placed" },
+                "tiles": ["p"],
+            },
+        });
+        let config = [
+            ("hidden", tile("snow.png", 0.0)),
+            ("placed", tile("snow.png", 0.0)),
+            ("patch", segment),
+        ];
+        let report = validate("unreachable", &config, &[]);
+        assert!(report.is_valid(), "{}", report);
+        assert!(mentions(&report.warnings, "config/hidden.json", "can never be generated"));
+        assert!(!mentions(&report.warnings, "config/placed.json", "can never be generated"));
+    }
+}